@@ -0,0 +1,273 @@
+use errors::MyErrorKind::DirectoryWalkError;
+use failure::Error;
+use failure::ResultExt;
+use ntfs::attributes::{
+    DosAttributes,
+    FilenameAttr,
+    ReparseAttr,
+};
+use ntfs::file_record::FileRecord;
+use ntfs::volumes::Volume;
+use rayon::prelude::*;
+use rusqlite::Connection;
+use slog::Logger;
+use sql::replace_files;
+use std::fs;
+use std::fs::Metadata;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::Mutex;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// the root keeps the record number it has on NTFS, so it is its own parent like there
+pub const ROOT_ID: u64 = 5;
+const WIN32_NAMESPACE: u8 = 1;
+const IN_USE: u16 = 0x01;
+const IN_USE_DIRECTORY: u16 = 0x03;
+
+#[derive(Debug, Default)]
+pub struct WalkReport {
+    /// Directories that could not be listed, their content is missing from the index.
+    pub unreadable: Vec<PathBuf>,
+}
+
+/// Indexes a tree of any filesystem by listing its directories, for volumes that have no MFT to read.
+/// Records get synthetic numbers, unique only within this walk.
+pub fn walk<P: AsRef<Path>>(root: P) -> Result<(Vec<FileRecord>, WalkReport), Error> {
+    walk_watched(root, &|_, _| {})
+}
+
+/// Same as `walk`, `watch` is called with every directory and its number right before it is listed,
+/// so nothing created in it afterwards can be missed.
+pub fn walk_watched<P: AsRef<Path>>(root: P, watch: &(Fn(&Path, u64) + Sync)) -> Result<(Vec<FileRecord>, WalkReport), Error> {
+    let root = root.as_ref();
+    let metadata = fs::metadata(root).context(DirectoryWalkError)?;
+    if !metadata.is_dir() {
+        Err(DirectoryWalkError)?;
+    }
+    let walker = Walker {
+        next_id: AtomicUsize::new(ROOT_ID as usize + 1),
+        unreadable: Mutex::new(Vec::new()),
+        watch,
+    };
+    let mut files = vec![file_record(ROOT_ID, ROOT_ID, ".".to_string(), &metadata, None)];
+    files.extend(walker.walk_dir(root, ROOT_ID));
+    let report = WalkReport { unreadable: walker.unreadable.into_inner().unwrap() };
+    Ok((files, report))
+}
+
+/// Walks the tree of `volume`, see `walk_watched`, and replaces what `conn` holds for it with the result.
+pub fn index(logger: &Logger, conn: &mut Connection, volume: &Volume, watch: &(Fn(&Path, u64) + Sync)) -> Result<Vec<FileRecord>, Error> {
+    info!(logger, "walk directory"; "status" => "started");
+    let (files, report) = walk_watched(&volume.name, watch)?;
+    if !report.unreadable.is_empty() {
+        warn!(logger, "skipped directories that could not be read"; "directories" => format!("{:?}", report.unreadable));
+    }
+    info!(logger, "walk directory"; "status" => "finished", "files count" => files.len());
+    replace_files(conn, volume, &files, None);
+    Ok(files)
+}
+
+/// The record of the file `name` in the directory numbered `parent_id`, None when it is gone already.
+pub fn read_file(dir: &Path, id: u64, parent_id: u64, name: &str) -> Option<FileRecord> {
+    let path = dir.join(name);
+    let metadata = fs::symlink_metadata(&path).ok()?;
+    let target = if metadata.file_type().is_symlink() { fs::read_link(&path).ok() } else { None };
+    Some(file_record(id, parent_id, name.to_string(), &metadata, target))
+}
+
+struct Walker<'a> {
+    next_id: AtomicUsize,
+    unreadable: Mutex<Vec<PathBuf>>,
+    watch: &'a (Fn(&Path, u64) + Sync),
+}
+
+impl<'a> Walker<'a> {
+    fn walk_dir(&self, dir: &Path, dir_id: u64) -> Vec<FileRecord> {
+        (self.watch)(dir, dir_id);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                self.unreadable.lock().unwrap().push(dir.to_path_buf());
+                return Vec::new();
+            }
+        };
+        let mut files = Vec::new();
+        let mut sub_dirs = Vec::new();
+        for entry in entries {
+            // symlinks are indexed but not followed, so the walk can't loop
+            let (entry, metadata) = match entry.and_then(|e| e.metadata().map(|m| (e, m))) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    self.unreadable.lock().unwrap().push(dir.to_path_buf());
+                    continue;
+                }
+            };
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed) as u64;
+            let path = entry.path();
+            let target = if metadata.file_type().is_symlink() { fs::read_link(&path).ok() } else { None };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if metadata.is_dir() {
+                sub_dirs.push((path, id));
+            }
+            files.push(file_record(id, dir_id, name, &metadata, target));
+        }
+        let nested: Vec<Vec<FileRecord>> = sub_dirs.par_iter()
+            .map(|(path, id)| self.walk_dir(path, *id))
+            .collect();
+        files.extend(nested.into_iter().flat_map(|n| n));
+        files
+    }
+}
+
+fn file_record(id: u64, parent_id: u64, name: String, metadata: &Metadata, target: Option<PathBuf>) -> FileRecord {
+    let mut file = FileRecord::default();
+    file.header.fr_number = id;
+    file.header.flags = if metadata.is_dir() { IN_USE_DIRECTORY } else { IN_USE };
+    file.data_attr.size = if metadata.is_dir() { 0 } else { metadata.len() as i64 };
+    file.standard_attr.modified = unix_time(metadata.modified());
+    file.standard_attr.created = unix_time(metadata.created());
+    file.standard_attr.accessed = unix_time(metadata.accessed());
+    file.standard_attr.dos_attributes = dos_attributes(metadata, &name);
+    file.reparse_attr = target.map(|target| ReparseAttr::Symlink {
+        relative: target.is_relative(),
+        target: target.to_string_lossy().into_owned(),
+    });
+    file.name_attrs.push(FilenameAttr {
+        parent_id: parent_id as i64,
+        dos_flags: file.standard_attr.dos_attributes.bits(),
+        namespace: WIN32_NAMESPACE,
+        name,
+    });
+    file
+}
+
+/// Seconds since the unix epoch, like the times read from the MFT. Times the filesystem doesn't keep,
+/// e.g. the creation time on most Linux filesystems, are left at 0.
+fn unix_time(time: ::std::io::Result<SystemTime>) -> i64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[cfg(windows)]
+fn dos_attributes(metadata: &Metadata, _name: &str) -> DosAttributes {
+    use std::os::windows::fs::MetadataExt;
+    DosAttributes::from_bits_truncate(metadata.file_attributes())
+}
+
+#[cfg(not(windows))]
+fn dos_attributes(metadata: &Metadata, name: &str) -> DosAttributes {
+    let mut attributes = DosAttributes::empty();
+    attributes.set(DosAttributes::READONLY, metadata.permissions().readonly());
+    attributes.set(DosAttributes::HIDDEN, name.starts_with('.'));
+    attributes.set(DosAttributes::REPARSE_POINT, metadata.file_type().is_symlink());
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    struct TempTree(PathBuf);
+
+    impl TempTree {
+        fn new(name: &str) -> Self {
+            let root = env::temp_dir().join(format!("cloppy-{}-{}", name, ::std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("docs").join("old")).unwrap();
+            fs::create_dir_all(root.join("music")).unwrap();
+            fs::write(root.join("readme.txt"), b"hello").unwrap();
+            fs::write(root.join("docs").join("report.txt"), b"report").unwrap();
+            fs::write(root.join("docs").join("old").join("draft.txt"), b"").unwrap();
+            TempTree(root)
+        }
+    }
+
+    impl Drop for TempTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn named<'a>(files: &'a [FileRecord], name: &str) -> &'a FileRecord {
+        files.iter().find(|f| f.name_attrs[0].name == name).unwrap()
+    }
+
+    #[test]
+    fn walk_links_every_file_to_its_parent() {
+        let tree = TempTree::new("walk-links");
+        let (files, report) = walk(&tree.0).unwrap();
+
+        assert!(report.unreadable.is_empty());
+        assert_eq!(7, files.len());
+        let root = named(&files, ".");
+        assert_eq!(ROOT_ID as i64, root.fr_number());
+        assert_eq!(ROOT_ID as i64, root.name_attrs[0].parent_id);
+        let docs = named(&files, "docs");
+        assert!(docs.is_directory());
+        assert_eq!(ROOT_ID as i64, docs.name_attrs[0].parent_id);
+        let old = named(&files, "old");
+        assert_eq!(docs.fr_number(), old.name_attrs[0].parent_id);
+        let draft = named(&files, "draft.txt");
+        assert!(!draft.is_directory());
+        assert_eq!(old.fr_number(), draft.name_attrs[0].parent_id);
+        assert_eq!(6, named(&files, "report.txt").data_attr.size);
+    }
+
+    #[test]
+    fn walk_gives_unique_ids() {
+        let tree = TempTree::new("walk-ids");
+        let (files, _) = walk(&tree.0).unwrap();
+
+        let mut ids: Vec<i64> = files.iter().map(|f| f.fr_number()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(files.len(), ids.len());
+    }
+
+    #[test]
+    fn every_directory_is_watched_before_it_is_listed() {
+        let tree = TempTree::new("walk-watched");
+        let watched = Mutex::new(Vec::new());
+        let (files, _) = walk_watched(&tree.0, &|path, id| watched.lock().unwrap().push((path.to_path_buf(), id))).unwrap();
+
+        let mut watched = watched.into_inner().unwrap();
+        watched.sort();
+        let old = named(&files, "old").fr_number() as u64;
+        let docs = named(&files, "docs").fr_number() as u64;
+        let music = named(&files, "music").fr_number() as u64;
+        assert_eq!(vec![
+            (tree.0.clone(), ROOT_ID),
+            (tree.0.join("docs"), docs),
+            (tree.0.join("docs").join("old"), old),
+            (tree.0.join("music"), music)], watched);
+    }
+
+    #[test]
+    fn walk_of_a_file_fails() {
+        let tree = TempTree::new("walk-file");
+        assert!(walk(tree.0.join("readme.txt")).is_err());
+    }
+
+    #[test]
+    fn times_are_unix_seconds() {
+        let tree = TempTree::new("walk-times");
+        let (files, _) = walk(&tree.0).unwrap();
+
+        let modified = fs::metadata(tree.0.join("readme.txt")).unwrap().modified().unwrap();
+        let seconds = modified.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        assert_eq!(seconds, named(&files, "readme.txt").standard_attr.modified);
+        assert_eq!(0, unix_time(Err(::std::io::ErrorKind::Other.into())));
+    }
+}
@@ -19,8 +19,14 @@ pub enum MyErrorKind {
     UserSettingsError,
     #[fail(display = "Error while processing change journal events.")]
     UsnJournalError,
-    #[fail(display = "UsnRecord v{} is not supported", _0)]
-    UsnRecordVersionUnsupported(u16),
+    #[fail(display = "Error while reading the volume.")]
+    VolumeReadError,
+    #[fail(display = "Invalid NTFS boot sector: {}", _0)]
+    InvalidBootSector(&'static str),
+    #[fail(display = "Invalid change script at line {}", _0)]
+    InvalidChangeScript(usize),
+    #[fail(display = "Error while walking the directory tree.")]
+    DirectoryWalkError,
 }
 
 //Boilerplate start
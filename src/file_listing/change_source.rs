@@ -0,0 +1,227 @@
+use errors::MyErrorKind::InvalidChangeScript;
+use failure::Error;
+use ntfs::attributes::FilenameAttr;
+use ntfs::change_journal::{
+    JournalLoss,
+    UsnChange,
+    UsnRecord,
+    UsnRename,
+};
+use ntfs::file_record::{
+    FileRecord,
+    RECORD_NUMBER_MASK,
+};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// attributes of the UsnRecord of a deleted directory or file, see `UsnRecord::is_dir`
+const DELETED_DIR_FLAGS: u32 = 0x10;
+const DELETED_FILE_FLAGS: u32 = 0x20;
+
+pub enum SourceEvent {
+    Changes(Vec<UsnChange>),
+    /// The source can't go on, the volume has to be indexed again.
+    Lost(JournalLoss),
+    /// The source can't tell what changed, the volume has to be indexed again to find out.
+    Rescan,
+    Finished,
+}
+
+/// Anything that tells the index what changed on a volume, one batch at a time.
+pub trait ChangeSource: Send {
+    /// Blocks until the next batch is available. An error is transient, the caller asks again later.
+    fn next_event(&mut self) -> Result<SourceEvent, Error>;
+}
+
+/// Replays changes written down in a text file, to drive the index without a live volume.
+///
+/// One change per line with tab separated fields, references are file reference numbers and kind is `file` or `dir`:
+/// `new|update <reference> <parent> <kind> <size> <name>`, `delete <reference> <parent> <kind> <name>` and
+/// `rename <reference> <kind> <old parent> <old name> <new parent> <new name>`. An empty line ends a batch
+/// and lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct ScriptedSource {
+    batches: VecDeque<Vec<UsnChange>>,
+}
+
+impl ScriptedSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let script = fs::read_to_string(path)?;
+        ScriptedSource::parse(&script)
+    }
+
+    pub fn parse(script: &str) -> Result<Self, Error> {
+        let mut batches = VecDeque::new();
+        let mut batch = Vec::new();
+        for (pos, line) in script.lines().enumerate() {
+            let line = line.trim_right_matches('\r');
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.is_empty() {
+                if !batch.is_empty() {
+                    batches.push_back(batch);
+                    batch = Vec::new();
+                }
+                continue;
+            }
+            batch.push(parse_change(line).ok_or(InvalidChangeScript(pos + 1))?);
+        }
+        if !batch.is_empty() {
+            batches.push_back(batch);
+        }
+        Ok(ScriptedSource { batches })
+    }
+}
+
+impl ChangeSource for ScriptedSource {
+    fn next_event(&mut self) -> Result<SourceEvent, Error> {
+        Ok(match self.batches.pop_front() {
+            Some(changes) => SourceEvent::Changes(changes),
+            None => SourceEvent::Finished,
+        })
+    }
+}
+
+/// Asks for the volume to be indexed again every `interval`, for trees whose changes can't be followed.
+pub struct RescanSource {
+    interval: Duration,
+}
+
+impl RescanSource {
+    pub fn new(interval: Duration) -> Self {
+        RescanSource { interval }
+    }
+}
+
+impl ChangeSource for RescanSource {
+    fn next_event(&mut self) -> Result<SourceEvent, Error> {
+        thread::sleep(self.interval);
+        Ok(SourceEvent::Rescan)
+    }
+}
+
+/// The record a `DELETE` change carries for the link `name` of `reference` in `parent`.
+pub fn deleted_record(reference: u64, parent: i64, is_dir: bool, name: &str) -> UsnRecord {
+    let mut record = UsnRecord::default();
+    record.fr_number = reference as i64;
+    record.mft_id = reference & RECORD_NUMBER_MASK;
+    record.parent_fr_number = parent;
+    record.flags = if is_dir { DELETED_DIR_FLAGS } else { DELETED_FILE_FLAGS };
+    record.name = name.to_string();
+    record
+}
+
+fn parse_change(line: &str) -> Option<UsnChange> {
+    let fields = line.split('\t').collect::<Vec<&str>>();
+    let change = match (fields[0], fields.len()) {
+        ("new", 6) => UsnChange::NEW(file_record(&fields[1..])?),
+        ("update", 6) => UsnChange::UPDATE(file_record(&fields[1..])?),
+        ("delete", 5) => UsnChange::DELETE(deleted_record(fields[1].parse().ok()?, fields[2].parse().ok()?, is_dir(fields[3])?, fields[4])),
+        ("rename", 7) => {
+            let rename = UsnRename {
+                old_parent: fields[3].parse().ok()?,
+                old_name: fields[4].to_string(),
+                new_parent: fields[5].parse().ok()?,
+                new_name: fields[6].to_string(),
+                file: file_record(&[fields[1], fields[5], fields[2], "0", fields[6]])?,
+            };
+            if rename.old_parent != rename.new_parent {
+                UsnChange::MOVE(rename)
+            } else {
+                UsnChange::RENAME(rename)
+            }
+        }
+        _ => return None,
+    };
+    Some(change)
+}
+
+/// `<reference> <parent> <kind> <size> <name>`
+fn file_record(fields: &[&str]) -> Option<FileRecord> {
+    let reference = fields[0].parse::<u64>().ok()?;
+    let mut file = FileRecord::default();
+    file.header.fr_number = reference & RECORD_NUMBER_MASK;
+    file.header.seq_number = (reference >> 48) as u16;
+    file.header.flags = if is_dir(fields[2])? { 0x03 } else { 0x01 };
+    file.data_attr.size = fields[3].parse().ok()?;
+    file.name_attrs.push(FilenameAttr {
+        parent_id: fields[1].parse().ok()?,
+        dos_flags: 0,
+        namespace: 1,
+        name: fields[4].to_string(),
+    });
+    Some(file)
+}
+
+fn is_dir(kind: &str) -> Option<bool> {
+    match kind {
+        "dir" => Some(true),
+        "file" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_is_split_in_batches() {
+        let script = "# two batches\nnew\t7\t5\tfile\t42\tmy file.txt\nnew\t8\t5\tdir\t0\tdocs\n\n\ndelete\t7\t5\tfile\tmy file.txt\n";
+        let mut source = ScriptedSource::parse(script).unwrap();
+
+        match source.next_event().unwrap() {
+            SourceEvent::Changes(changes) => {
+                assert_eq!(2, changes.len());
+                match changes[0] {
+                    UsnChange::NEW(ref file) => {
+                        assert_eq!(7, file.header.fr_number);
+                        assert_eq!(42, file.data_attr.size);
+                        assert_eq!("my file.txt", file.name_attrs[0].name);
+                    }
+                    _ => panic!("expected a new file"),
+                }
+            }
+            _ => panic!("expected changes"),
+        }
+        match source.next_event().unwrap() {
+            SourceEvent::Changes(changes) => match changes[0] {
+                UsnChange::DELETE(ref record) => assert!(!record.is_dir()),
+                _ => panic!("expected a delete"),
+            },
+            _ => panic!("expected changes"),
+        }
+        match source.next_event().unwrap() {
+            SourceEvent::Finished => {}
+            _ => panic!("expected the end of the script"),
+        }
+    }
+
+    #[test]
+    fn rename_to_another_parent_is_a_move() {
+        let script = "rename\t7\tfile\t5\ta.txt\t5\tb.txt\nrename\t7\tfile\t5\tb.txt\t6\tb.txt";
+        match ScriptedSource::parse(script).unwrap().next_event().unwrap() {
+            SourceEvent::Changes(changes) => {
+                match changes[0] {
+                    UsnChange::RENAME(ref rename) => assert_eq!("b.txt", rename.file.name_attrs[0].name),
+                    _ => panic!("expected a rename"),
+                }
+                match changes[1] {
+                    UsnChange::MOVE(ref rename) => assert_eq!(6, rename.file.name_attrs[0].parent_id),
+                    _ => panic!("expected a move"),
+                }
+            }
+            _ => panic!("expected changes"),
+        }
+    }
+
+    #[test]
+    fn invalid_line_is_reported() {
+        let error = ScriptedSource::parse("new\t7\t5\tfile\t42\tok.txt\nnew\t8\tlink\tfile\t0\tbad.txt").err().unwrap();
+        assert_eq!("Invalid change script at line 2", error.to_string());
+    }
+}
@@ -1,5 +1,8 @@
+use ntfs::attributes::DosAttributes;
+use ntfs::attributes::ReparseAttr;
 use ntfs::change_journal::UsnRecord;
 use ntfs::file_record::FileRecord;
+use ntfs::file_record::RECORD_NUMBER_MASK;
 use rusqlite::Result;
 use rusqlite::Row;
 use std::u32;
@@ -12,14 +15,24 @@ pub struct FileEntity {
     parent_id: FileId,
     size: i64,
     id: FileId,
-    _id: u32,
+    _id: u64,
     flags: u16,
+    link_kind: LinkKind,
+    link_target: Option<String>,
+    modified: i64,
+    created: i64,
+    accessed: i64,
+    dos_attributes: DosAttributes,
+    stream_count: u16,
 }
 
 
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialOrd, PartialEq, Hash)]
 pub struct FileId {
-    id: u32,
+    volume: u8,
+    id: u64,
+    seq: u16,
+    link: u16,
     f_type: FileType,
 }
 
@@ -28,30 +41,106 @@ pub struct FileId {
 pub enum FileType {
     DIRECTORY,
     FILE,
+    STREAM,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LinkKind {
+    NONE,
+    SYMLINK,
+    JUNCTION,
+    WSL,
+    OTHER,
+}
+
+impl LinkKind {
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_code(code: u8) -> LinkKind {
+        match code {
+            0 => LinkKind::NONE,
+            1 => LinkKind::SYMLINK,
+            2 => LinkKind::JUNCTION,
+            3 => LinkKind::WSL,
+            _ => LinkKind::OTHER,
+        }
+    }
+}
+
+impl<'a> From<&'a ReparseAttr> for LinkKind {
+    fn from(reparse: &ReparseAttr) -> Self {
+        match *reparse {
+            ReparseAttr::Symlink { .. } => LinkKind::SYMLINK,
+            ReparseAttr::MountPoint { .. } => LinkKind::JUNCTION,
+            ReparseAttr::LxSymlink { .. } => LinkKind::WSL,
+            ReparseAttr::Other(_) => LinkKind::OTHER,
+        }
+    }
 }
 
 impl From<UsnRecord> for FileId {
     fn from(record: UsnRecord) -> Self {
         if record.is_dir() {
-            FileId::directory(record.mft_id)
+            FileId::directory(record.fr_number as u64)
         } else {
-            FileId::file(record.mft_id)
+            FileId::file(record.fr_number as u64)
         }
     }
 }
 
 
 impl FileId {
-    pub fn file(id: u32) -> FileId {
-        FileId { id, f_type: FileType::FILE }
+    /// `reference` is a file reference number: the 48 bits record number and its 16 bits sequence number on top.
+    pub fn file(reference: u64) -> FileId {
+        FileId::new(reference, FileType::FILE)
     }
-    pub fn directory(id: u32) -> FileId {
-        FileId { id, f_type: FileType::DIRECTORY }
+    pub fn directory(reference: u64) -> FileId {
+        FileId::new(reference, FileType::DIRECTORY)
     }
-    pub fn id(&self) -> u32 {
+    pub fn stream(reference: u64) -> FileId {
+        FileId::new(reference, FileType::STREAM)
+    }
+    fn new(reference: u64, f_type: FileType) -> FileId {
+        FileId { volume: 0, id: reference & RECORD_NUMBER_MASK, seq: (reference >> 48) as u16, link: 0, f_type }
+    }
+    pub fn with_link(self, link: u16) -> FileId {
+        FileId { link, ..self }
+    }
+    pub fn on_volume(self, volume: u8) -> FileId {
+        FileId { volume, ..self }
+    }
+    /// The first stream of the same record.
+    pub fn to_stream(self) -> FileId {
+        FileId { link: 0, f_type: FileType::STREAM, ..self }
+    }
+    /// The record number, without the sequence number.
+    pub fn id(&self) -> u64 {
         self.id
     }
 
+    pub fn seq(&self) -> u16 {
+        self.seq
+    }
+
+    pub fn reference(&self) -> u64 {
+        self.id | (self.seq as u64) << 48
+    }
+
+    /// Both ids belong to the same record, the same use of an MFT slot on the same volume.
+    pub fn same_record(&self, other: &FileId) -> bool {
+        self.volume == other.volume && self.id == other.id && self.seq == other.seq
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn link(&self) -> u16 {
+        self.link
+    }
+
     pub fn f_type(&self) -> FileType {
         self.f_type
     }
@@ -60,40 +149,129 @@ impl FileId {
 impl From<FileRecord> for FileEntity {
     fn from(file: FileRecord) -> Self {
         let fr_number = file.fr_number();
-        let name = file.name_attrs.into_iter()
-            .filter(|n| n.namespace != DOS_NAMESPACE)
-            .take(1)
+        FileEntity::links(file).into_iter()
             .next()
-            .expect(&format!("Found a file record without name: {}", fr_number));
+            .expect(&format!("Found a file record without name: {}", fr_number))
+    }
+}
+
+impl FileEntity {
+    /// One entity per hard link, numbered in the order of the non DOS names of the record,
+    /// followed by one entity per named stream shown as `name:stream` next to the first link.
+    pub fn links(file: FileRecord) -> Vec<FileEntity> {
         let id = if file.header.flags & 0x02 != 0 {
-            FileId::directory(file.header.fr_number)
+            FileId::directory(file.fr_number() as u64)
         } else {
-            FileId::file(file.header.fr_number)
+            FileId::file(file.fr_number() as u64)
         };
+        let size = file.data_attr.size;
+        let flags = file.header.flags;
+        let link_kind = file.reparse_attr.as_ref().map_or(LinkKind::NONE, LinkKind::from);
+        let link_target = file.reparse_attr.as_ref().and_then(|r| r.target()).map(str::to_string);
+        let standard = file.standard_attr;
+        let stream_count = file.streams.len() as u16;
+        let mut entries = file.name_attrs.into_iter()
+            .filter(|n| n.namespace != DOS_NAMESPACE)
+            .enumerate()
+            .map(|(link, name)| FileEntity {
+                name: name.name,
+                parent_id: FileId::directory(name.parent_id as u64),
+                size,
+                id: id.with_link(link as u16),
+                _id: u64::MAX,
+                flags,
+                link_kind,
+                link_target: link_target.clone(),
+                modified: standard.modified,
+                created: standard.created,
+                accessed: standard.accessed,
+                dos_attributes: standard.dos_attributes,
+                stream_count,
+            })
+            .collect::<Vec<FileEntity>>();
+        let streams = match entries.first() {
+            Some(first) => file.streams.into_iter()
+                .enumerate()
+                .map(|(pos, stream)| first.stream(pos as u16, &stream.name, stream.size))
+                .collect(),
+            None => Vec::new(),
+        };
+        entries.extend(streams);
+        entries
+    }
+
+    fn stream(&self, pos: u16, name: &str, size: i64) -> FileEntity {
         FileEntity {
-            name: name.name,
-            parent_id: FileId::directory(name.parent_id as u32),
-            size: file.data_attr.size,
-            id,
-            _id: u32::MAX,
-            flags: file.header.flags,
+            name: format!("{}:{}", self.name, name),
+            parent_id: self.parent_id,
+            size,
+            id: self.id.to_stream().with_link(pos),
+            _id: u64::MAX,
+            flags: self.flags & !0x02,
+            link_kind: LinkKind::NONE,
+            link_target: None,
+            modified: self.modified,
+            created: self.created,
+            accessed: self.accessed,
+            dos_attributes: self.dos_attributes,
+            stream_count: 0,
         }
     }
-}
 
-impl FileEntity {
+    pub fn from_stream_row(row: &Row) -> Result<FileEntity> {
+        let id = row.get::<i32, i64>(0) as u64;
+        let pos = row.get::<i32, u16>(1);
+        let stream_name = row.get::<i32, String>(2);
+        let size = row.get::<i32, i64>(3);
+        let volume = row.get::<i32, u8>(11);
+        let file = FileEntity {
+            name: row.get::<i32, String>(5),
+            parent_id: FileId::directory(row.get::<i32, i64>(4) as u64).on_volume(volume),
+            size,
+            id: FileId::file(id).on_volume(volume),
+            _id: u64::MAX,
+            flags: row.get::<i32, u16>(6),
+            link_kind: LinkKind::NONE,
+            link_target: None,
+            modified: row.get::<i32, i64>(7),
+            created: row.get::<i32, i64>(8),
+            accessed: row.get::<i32, i64>(9),
+            dos_attributes: DosAttributes::from_bits_truncate(row.get::<i32, u32>(10)),
+            stream_count: 0,
+        };
+        Ok(file.stream(pos, &stream_name, size))
+    }
+
     pub fn from_file_row(row: &Row) -> Result<FileEntity> {
-        let _id = row.get::<i32, u32>(0);
-        let parent_id = FileId::directory(row.get::<i32, i64>(2) as u32);
+        let _id = row.get::<i32, i64>(0) as u64;
+        let parent_id = FileId::directory(row.get::<i32, i64>(2) as u64);
         let size = row.get::<i32, i64>(4);
         let name = row.get::<i32, String>(5);
+        let modified = row.get::<i32, i64>(6);
+        let created = row.get::<i32, i64>(7);
         let flags = row.get::<i32, u16>(8);
+        let link = row.get::<i32, u16>(12);
+        let link_kind = LinkKind::from_code(row.get::<i32, u8>(13));
+        let link_target = row.get::<i32, Option<String>>(14);
+        let accessed = row.get::<i32, i64>(15);
+        let dos_attributes = DosAttributes::from_bits_truncate(row.get::<i32, u32>(17));
+        let stream_count = row.get::<i32, u16>(22);
+        let volume = row.get::<i32, u8>(23);
+        let fr_number = row.get::<i32, i64>(10) as u64;
         let id = if flags & 0x02 != 0 {
-            FileId::directory(row.get::<i32, u32>(1))
+            FileId::directory(fr_number)
         } else {
-            FileId::file(row.get::<i32, u32>(1))
+            FileId::file(fr_number)
         };
-        Ok(FileEntity { name, parent_id, size, id, _id, flags })
+        let parent_id = parent_id.on_volume(volume);
+        Ok(FileEntity { name, parent_id, size, id: id.with_link(link).on_volume(volume), _id, flags, link_kind, link_target, modified, created, accessed, dos_attributes, stream_count })
+    }
+
+    /// Tags the entity, and the directory it is in, with the volume it was read from.
+    pub fn on_volume(mut self, volume: u8) -> FileEntity {
+        self.id = self.id.on_volume(volume);
+        self.parent_id = self.parent_id.on_volume(volume);
+        self
     }
 
     pub fn id(&self) -> FileId {
@@ -115,26 +293,160 @@ impl FileEntity {
     pub fn flags(&self) -> u16 {
         self.flags
     }
+
+    pub fn link_kind(&self) -> LinkKind {
+        self.link_kind
+    }
+
+    pub fn link_target(&self) -> Option<&str> {
+        self.link_target.as_ref().map(String::as_str)
+    }
+
+    pub fn modified(&self) -> i64 {
+        self.modified
+    }
+
+    pub fn created(&self) -> i64 {
+        self.created
+    }
+
+    pub fn accessed(&self) -> i64 {
+        self.accessed
+    }
+
+    pub fn dos_attributes(&self) -> DosAttributes {
+        self.dos_attributes
+    }
+
+    pub fn stream_count(&self) -> u16 {
+        self.stream_count
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use ntfs::attributes::FilenameAttr;
+    use ntfs::attributes::StreamAttr;
     use super::*;
 
+    fn name(name: &str, parent_id: i64, namespace: u8) -> FilenameAttr {
+        FilenameAttr { parent_id, dos_flags: 0, namespace, name: name.to_string() }
+    }
+
+    #[test]
+    fn one_entity_per_hard_link() {
+        let mut file = FileRecord::default();
+        file.header.fr_number = 42;
+        file.header.flags = 0x01;
+        file.name_attrs = vec![name("LINK~1.TXT", 5, DOS_NAMESPACE), name("link.txt", 5, 1), name("other.txt", 7, 0)];
+
+        let links = FileEntity::links(file);
+
+        assert_eq!(2, links.len());
+        assert_eq!(FileId::file(42), links[0].id());
+        assert_eq!("link.txt", links[0].name());
+        assert_eq!(FileId::directory(5), links[0].parent_id());
+        assert_eq!(FileId::file(42).with_link(1), links[1].id());
+        assert_eq!("other.txt", links[1].name());
+        assert_eq!(FileId::directory(7), links[1].parent_id());
+    }
+
+    #[test]
+    fn links_carry_reparse_target() {
+        let mut file = FileRecord::default();
+        file.header.flags = 0x03;
+        file.name_attrs = vec![name("Documents", 5, 1)];
+        file.reparse_attr = Some(ReparseAttr::MountPoint { target: "C:\\Users\\Public".to_string() });
+
+        let links = FileEntity::links(file);
+
+        assert_eq!(LinkKind::JUNCTION, links[0].link_kind());
+        assert_eq!(Some("C:\\Users\\Public"), links[0].link_target());
+    }
+
+    #[test]
+    fn streams_follow_links() {
+        let mut file = FileRecord::default();
+        file.header.fr_number = 42;
+        file.name_attrs = vec![name("setup.exe", 5, 1), name("copy.exe", 7, 1)];
+        file.streams = vec![StreamAttr { name: "Zone.Identifier".to_string(), size: 26 }];
+
+        let entries = FileEntity::links(file);
+
+        assert_eq!(3, entries.len());
+        assert_eq!(1, entries[1].stream_count());
+        assert_eq!(FileId::stream(42), entries[2].id());
+        assert_eq!("setup.exe:Zone.Identifier", entries[2].name());
+        assert_eq!(FileId::directory(5), entries[2].parent_id());
+        assert_eq!(26, entries[2].size());
+    }
+
+    #[test]
+    fn streams_keep_the_volume() {
+        let mut file = FileRecord::default();
+        file.header.fr_number = 42;
+        file.name_attrs = vec![name("setup.exe", 5, 1)];
+        file.streams = vec![StreamAttr { name: "Zone.Identifier".to_string(), size: 26 }];
+
+        let entries = FileEntity::links(file).into_iter().map(|f| f.on_volume(2)).collect::<Vec<FileEntity>>();
+
+        assert_eq!(FileId::file(42).on_volume(2), entries[0].id());
+        assert_eq!(FileId::directory(5).on_volume(2), entries[0].parent_id());
+        assert_eq!(FileId::stream(42).on_volume(2), entries[1].id());
+        assert_ne!(FileId::file(42), entries[0].id());
+    }
+
+    #[test]
+    fn file_id_keeps_the_whole_reference() {
+        let id = FileId::file(0x0002_0001_0000_0005);
+        assert_eq!(0x1_0000_0005, id.id());
+        assert_eq!(2, id.seq());
+        assert_eq!(0x0002_0001_0000_0005, id.reference());
+        assert!(!id.same_record(&FileId::file(0x0003_0001_0000_0005)));
+        assert!(id.same_record(&id.with_link(1)));
+    }
+
+    #[test]
+    fn links_of_reused_slot_have_new_sequence() {
+        let mut file = FileRecord::default();
+        file.header.fr_number = 42;
+        file.header.seq_number = 7;
+        file.name_attrs = vec![name("new.txt", 5 | 1 << 48, 1)];
+
+        let links = FileEntity::links(file);
+
+        assert_eq!(FileId::file(42 | 7 << 48), links[0].id());
+        assert_ne!(FileId::file(42), links[0].id());
+        assert_eq!(FileId::directory(5 | 1 << 48), links[0].parent_id());
+    }
+
+    #[test]
+    fn links_keep_records_above_32_bits() {
+        let mut file = FileRecord::default();
+        file.header.fr_number = 0x1_0000_0005;
+        file.header.seq_number = 2;
+        file.name_attrs = vec![name("far.txt", 5, 1)];
+
+        let links = FileEntity::links(file);
+
+        assert_eq!(FileId::file(0x0002_0001_0000_0005), links[0].id());
+        assert_ne!(FileId::file(5 | 2 << 48), links[0].id());
+    }
+
     #[test]
     fn usn_record_to_file_id_file() {
         let mut record = UsnRecord::default();
-        record.mft_id = 99;
-        record.flags = 0x30;
-        assert_eq!(FileId::file(99), record.into());
+        record.fr_number = 99 | 3 << 48;
+        record.flags = 0x20;
+        assert_eq!(FileId::file(99 | 3 << 48), record.into());
     }
 
     #[test]
     fn usn_record_to_file_id_dir() {
         let mut record = UsnRecord::default();
-        record.mft_id = 99;
-        record.flags = 0x16;
+        record.fr_number = 99;
+        record.flags = 0x10;
         assert_eq!(FileId::directory(99), record.into());
     }
 }
\ No newline at end of file
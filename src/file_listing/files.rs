@@ -1,9 +1,20 @@
 use file_listing::file_entity::FileEntity;
 use file_listing::file_entity::FileId;
+use file_listing::file_entity::FileType;
+use file_listing::file_entity::LinkKind;
+use file_listing::names::NamesMemory;
+use file_listing::paths::DirPath;
+use file_listing::paths::PathCache;
+use file_listing::query::Query;
+use file_listing::storage::Compaction;
 use file_listing::storage::Storage;
 use file_listing::storage::StorageItem;
+use ntfs::change_journal::UsnChange;
+use ntfs::attributes::DosAttributes;
 use std::borrow::Borrow;
 use std::cmp::Ordering;
+use std::iter;
+use std::sync::Arc;
 use twoway;
 
 #[derive(Debug, Eq)]
@@ -13,12 +24,23 @@ pub struct FileData {
     name_id: NameId,
     size: i64,
     flags: u16,
+    link_kind: LinkKind,
+    has_streams: bool,
     deleted: bool,
+    modified: u32,
+    created: u32,
+    accessed: u32,
+    dos_attributes: u16,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct NameId(pub u32);
 
+/// Unix seconds squeezed into 32 bits, dates before 1970 are kept as 0.
+fn compact_time(time: i64) -> u32 {
+    time.max(0).min(u32::max_value() as i64) as u32
+}
+
 impl PartialOrd for FileData {
     fn partial_cmp(&self, other: &FileData) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -46,7 +68,13 @@ impl FileData {
             size,
             flags,
             deleted,
+            link_kind: LinkKind::NONE,
+            has_streams: false,
             name_id: NameId(0),
+            modified: 0,
+            created: 0,
+            accessed: 0,
+            dos_attributes: 0,
         }
     }
 
@@ -54,6 +82,18 @@ impl FileData {
         self.name_id = name_id;
     }
 
+    pub fn set_parent_id(&mut self, parent_id: FileId) {
+        self.parent_id = parent_id;
+    }
+
+    pub fn set_link_kind(&mut self, link_kind: LinkKind) {
+        self.link_kind = link_kind;
+    }
+
+    pub fn set_has_streams(&mut self, has_streams: bool) {
+        self.has_streams = has_streams;
+    }
+
     pub fn set_deleted(&mut self, deleted: bool) {
         self.deleted = deleted;
     }
@@ -81,6 +121,31 @@ impl FileData {
     pub fn flags(&self) -> u16 {
         self.flags
     }
+
+    pub fn link_kind(&self) -> LinkKind {
+        self.link_kind
+    }
+
+    pub fn has_streams(&self) -> bool {
+        self.has_streams
+    }
+
+    pub fn modified(&self) -> u32 {
+        self.modified
+    }
+
+    pub fn created(&self) -> u32 {
+        self.created
+    }
+
+    pub fn accessed(&self) -> u32 {
+        self.accessed
+    }
+
+    pub fn dos_attributes(&self) -> DosAttributes {
+        DosAttributes::from_bits_truncate(self.dos_attributes as u32)
+    }
+
     pub fn is_root(&self) -> bool {
         self.parent_id == self.id
     }
@@ -97,60 +162,203 @@ impl From<FileEntity> for FileData {
             size: f.size(),
             id: f.id(),
             flags: f.flags(),
+            link_kind: f.link_kind(),
+            has_streams: f.stream_count() > 0,
             deleted: false,
             name_id: NameId(0),
+            modified: compact_time(f.modified()),
+            created: compact_time(f.created()),
+            accessed: compact_time(f.accessed()),
+            dos_attributes: f.dos_attributes().bits() as u16,
         }
     }
 }
 
 pub struct Files {
     separator: String,
+    show_streams: bool,
+    volumes: Vec<String>,
     storage: Storage,
+    paths: PathCache,
 }
 
 unsafe impl Send for Files {}
 
 impl Files {
-    pub fn new(_count: usize) -> Self {
+    /// `volumes` are the drive letters or mount points paths start with, by volume index.
+    pub fn new(_count: usize, show_streams: bool, volumes: Vec<String>) -> Self {
         let storage = Storage::new();
         let separator = "\\".to_owned();
-        Files { storage, separator }
+        Files { storage, separator, show_streams, volumes, paths: PathCache::default() }
+    }
+
+    pub fn show_streams(&self) -> bool {
+        self.show_streams
+    }
+
+    pub fn names_memory(&self) -> NamesMemory {
+        self.storage.names_memory()
+    }
+
+    /// One step of reclaiming deleted files and unused names, see `Storage::compact`.
+    pub fn compact(&mut self) -> Compaction {
+        let compaction = self.storage.compact();
+        if compaction.files > 0 {
+            self.paths.clear();
+        }
+        compaction
     }
 
     pub fn bulk_add(&mut self, files: Vec<FileEntity>) {
+        self.paths.clear();
         self.storage.bulk_insert(files);
     }
 
-    pub fn add_file(&mut self, f: FileEntity) {
-        self.storage.upsert(f.clone().into(), f.name());
+    /// Takes a fresh scan of `volume`, the changes applied to other volumes meanwhile are kept.
+    pub fn replace_volume(&mut self, volume: u8, files: Vec<FileEntity>) {
+        self.paths.clear();
+        self.storage.replace_volume(volume, files);
+    }
+
+    pub fn add_file(&mut self, links: Vec<FileEntity>) {
+        let show_streams = self.show_streams;
+        for f in links.into_iter().filter(|f| show_streams || f.id().f_type() != FileType::STREAM) {
+            if self.moves_directory(&f) {
+                self.invalidate_paths(f.id());
+            }
+            self.storage.set_link_target(f.id(), f.link_target());
+            self.storage.upsert(f.clone().into(), f.name());
+        }
+    }
+
+    pub fn update_file(&mut self, links: Vec<FileEntity>) {
+        if let Some(f) = links.first() {
+            let streams = links.iter().filter(|l| l.id().f_type() == FileType::STREAM).count();
+            self.storage.truncate_links(f.id(), (links.len() - streams) as u16);
+            self.storage.truncate_links(f.id().to_stream(), streams as u16);
+        }
+        self.add_file(links);
+    }
+
+    /// Applies a rename or a move to the link in place, so paths of whatever is below it follow.
+    /// Streams carry the name of their file, so a file with streams is updated as a whole.
+    pub fn rename_file(&mut self, links: Vec<FileEntity>, old_parent: FileId, old_name: &str, new_parent: FileId, new_name: &str) {
+        let renamed = match links.first() {
+            Some(f) => self.storage.rename_link(f.id(), old_parent, old_name, new_parent, new_name),
+            None => false,
+        };
+        if renamed {
+            self.invalidate_paths(links[0].id());
+        }
+        let has_streams = links.iter().any(|l| l.id().f_type() == FileType::STREAM);
+        if !renamed || has_streams {
+            self.update_file(links);
+        }
     }
 
-    pub fn update_file(&mut self, f: FileEntity) {
-        self.storage.upsert(f.clone().into(), f.name());
+    /// Applies a batch of changes read from `volume`.
+    pub fn apply_changes(&mut self, volume: u8, changes: Vec<UsnChange>) {
+        let links = |file| FileEntity::links(file).into_iter().map(|f| f.on_volume(volume)).collect::<Vec<FileEntity>>();
+        for change in changes {
+            match change {
+                UsnChange::DELETE(record) => {
+                    let parent_id = FileId::directory(record.parent_fr_number as u64).on_volume(volume);
+                    let name = record.name.clone();
+                    let id = FileId::from(record).on_volume(volume);
+                    self.delete_link(id, parent_id, &name);
+                    self.delete_streams(id);
+                }
+                UsnChange::RENAME(rename) | UsnChange::MOVE(rename) => {
+                    let old_parent = FileId::directory(rename.old_parent as u64).on_volume(volume);
+                    let new_parent = FileId::directory(rename.new_parent as u64).on_volume(volume);
+                    self.rename_file(links(rename.file), old_parent, &rename.old_name, new_parent, &rename.new_name);
+                }
+                UsnChange::UPDATE(file) => self.update_file(links(file)),
+                UsnChange::NEW(file) => self.add_file(links(file)),
+                UsnChange::IGNORE => {}
+            }
+        }
     }
 
     pub fn get_file<T: Borrow<FileId>>(&self, pos: T) -> StorageItem {
         self.storage.get(pos)
     }
 
+    pub fn link_target(&self, id: FileId) -> Option<&str> {
+        self.storage.link_target(id)
+    }
+
     pub fn delete_file(&mut self, id: FileId) {
+        self.invalidate_paths(id);
         self.storage.delete(id);
     }
 
-    pub fn path_of(&self, file: &FileData) -> String {
-        let mut result = String::new();
-        let mut parents: Vec<&str> = Vec::new();
-        let mut current = file;
-        while !current.is_root() {
-            let item = self.storage.get(current.parent_id());
-            parents.push(item.name);
-            current = item.data;
+    /// Returns false when the link isn't in the index.
+    pub fn delete_link(&mut self, id: FileId, parent_id: FileId, name: &str) -> bool {
+        self.invalidate_paths(id);
+        self.storage.delete_link(id, parent_id, name)
+    }
+
+    pub fn delete_streams(&mut self, id: FileId) {
+        self.storage.truncate_links(id.to_stream(), 0);
+    }
+
+    /// The links and streams directly in the directory `dir`.
+    pub fn children_of(&self, dir: FileId) -> &[FileId] {
+        self.storage.children_of(dir)
+    }
+
+    /// Everything below the directory `dir`, e.g. to search within a folder.
+    pub fn descendants_of(&self, dir: FileId) -> Vec<FileId> {
+        self.storage.descendants_of(dir)
+    }
+
+    /// The path of the directory holding `file`, shared with everything else in that directory.
+    pub fn path_of(&self, file: &FileData) -> Arc<DirPath> {
+        if file.is_root() {
+            return Arc::new(DirPath::new(String::new()));
         }
-        for p in parents.into_iter().rev() {
-            result.push_str(p);
-            result.push_str(&self.separator);
+        self.dir_path(file.parent_id())
+    }
+
+    /// The cached path of `dir`, built from the path of its parent the first time. The path of a directory
+    /// missing from the index is left out, and not cached as it may still arrive.
+    fn dir_path(&self, dir: FileId) -> Arc<DirPath> {
+        if let Some(path) = self.paths.get(dir) {
+            return path;
         }
-        result
+        let item = match self.storage.find(dir) {
+            Some(item) => item,
+            None => return Arc::new(DirPath::new(String::new())),
+        };
+        let mut text = match item.data.is_root() {
+            true => String::new(),
+            false => self.dir_path(item.data.parent_id()).text.clone(),
+        };
+        match self.volumes.get(dir.volume() as usize) {
+            Some(volume) if item.data.is_root() => text.push_str(volume),
+            _ => text.push_str(item.name),
+        }
+        text.push_str(&self.separator);
+        self.paths.insert(dir, DirPath::new(text))
+    }
+
+    /// The directory `f` is known already under another parent or name.
+    fn moves_directory(&self, f: &FileEntity) -> bool {
+        f.id().f_type() == FileType::DIRECTORY && self.storage.find(f.id())
+            .map_or(false, |old| old.data.parent_id() != f.parent_id() || old.name != f.name())
+    }
+
+    /// Drops the cached paths of the directory `id` and of the directories below it.
+    fn invalidate_paths(&self, id: FileId) {
+        if id.f_type() != FileType::DIRECTORY || self.paths.is_empty() {
+            return;
+        }
+        let dir = id.with_link(0);
+        let below = self.storage.descendants_of(dir).into_iter()
+            .filter(|id| id.f_type() == FileType::DIRECTORY)
+            .map(|id| id.with_link(0));
+        self.paths.remove(iter::once(dir).chain(below));
     }
 
 //    fn new_search_by_name<'a>(&self, name: &'a str) -> Vec<ItemId> {
@@ -173,8 +381,18 @@ impl Files {
 
 
     pub fn search_by_name<'a>(&self, name: &'a str, _prev_search: Option<&[FileId]>) -> Vec<FileId> {
+        self.storage.seal();
         self.storage.iter()
-            .filter(|item| twoway::find_str(item.name, name).is_some())
+            .filter(|item| !item.data.deleted() && twoway::find_str(item.name, name).is_some())
+            .map(|i| i.data.id())
+            .collect()
+    }
+
+    pub fn search(&self, query: &Query) -> Vec<FileId> {
+        self.storage.seal();
+        self.storage.iter()
+            .filter(|item| !item.data.deleted() && query.matches(item.name, item.data))
+            .filter(|item| query.path.is_none() || query.matches_path(&self.path_of(item.data).text))
             .map(|i| i.data.id())
             .collect()
     }
@@ -194,8 +412,14 @@ impl Files {
 
 #[cfg(test)]
 mod tests {
+    use file_listing::change_source::{
+        ChangeSource,
+        ScriptedSource,
+        SourceEvent,
+    };
     use file_listing::file_entity::FileId;
     use ntfs::attributes::FilenameAttr;
+    use ntfs::attributes::StreamAttr;
     use ntfs::file_record::FileRecord;
     use super::*;
 
@@ -203,7 +427,7 @@ mod tests {
     const DIR: u16 = 2;
 
     fn test_data() -> Files {
-        let mut files = Files::new(1);
+        let mut files = Files::new(1, true, Vec::new());
         let file0 = FileData::new(FileId::file(0), FileId::directory(1), 0, FILE, false);
         let dir0 = FileData::new(FileId::directory(0), FileId::directory(1), 0, DIR, false);
         let file1 = FileData::new(FileId::file(1), FileId::directory(1), 0, FILE, false);
@@ -235,14 +459,14 @@ mod tests {
         FileEntity::from(new_file_record(name))
     }
 
-    fn new_dir(name: &str, id: u32) -> FileEntity {
+    fn new_dir(name: &str, id: u64) -> FileEntity {
         let mut entry = new_file_record(name);
         entry.header.flags = 0x02;
         entry.header.fr_number = id;
         FileEntity::from(entry)
     }
 
-    fn new_file_with_parent(name: &str, id: u32, parent: u32) -> FileEntity {
+    fn new_file_with_parent(name: &str, id: u64, parent: u64) -> FileEntity {
         let mut entry = new_file_record(name);
         entry.name_attrs[0].parent_id = parent as i64;
         entry.header.fr_number = id;
@@ -251,7 +475,7 @@ mod tests {
 
     #[test]
     fn empty_files() {
-        let files = Files::new(5);
+        let files = Files::new(5, false, Vec::new());
         assert!(files.search_by_name("", None).is_empty())
     }
 
@@ -273,11 +497,46 @@ mod tests {
         let files = test_data();
 
         let f = files.get_file(FileId::file(0)).data;
-        assert_eq!("dir1\\", files.path_of(f));
+        assert_eq!("dir1\\", files.path_of(f).text);
         let f = files.get_file(FileId::file(1)).data;
-        assert_eq!("dir1\\", files.path_of(f));
+        assert_eq!("dir1\\", files.path_of(f).text);
         let f = files.get_file(FileId::directory(3)).data;
-        assert_eq!("dir1\\dir2\\", files.path_of(f));
+        assert_eq!("dir1\\dir2\\", files.path_of(f).text);
+    }
+
+    #[test]
+    fn paths_start_with_the_volume() {
+        let mut files = Files::new(1, false, vec!["C:".to_string(), "D:".to_string()]);
+        let mut root = new_file_record(".");
+        root.header.flags = 0x02;
+        root.header.fr_number = 5;
+        root.name_attrs[0].parent_id = 5;
+        files.add_file(vec![FileEntity::from(root).on_volume(1)]);
+        files.add_file(vec![new_file_with_parent("data.csv", 7, 5).on_volume(1)]);
+        files.add_file(vec![new_file_with_parent("data.csv", 7, 9)]);
+
+        let search = files.search_by_name("data.csv", None);
+        assert_eq!(vec![FileId::file(7), FileId::file(7).on_volume(1)], search);
+        assert_eq!("D:\\", files.path_of(files.get_file(search[1]).data).text);
+    }
+
+    #[test]
+    fn replacing_a_volume_keeps_the_others() {
+        let mut files = Files::new(1, false, vec!["C:".to_string(), "D:".to_string(), "E:".to_string()]);
+        files.bulk_add(vec![new_file_with_parent("c.txt", 7, 5), new_file_with_parent("d.txt", 7, 5).on_volume(1),
+                            new_file_with_parent("e.txt", 7, 5).on_volume(2)]);
+        files.add_file(vec![new_file_with_parent("c_new.txt", 8, 5)]);
+        files.add_file(vec![new_file_with_parent("e_new.txt", 8, 5).on_volume(2)]);
+
+        files.replace_volume(1, vec![new_file_with_parent("d2.txt", 9, 5).on_volume(1), new_dir("dir", 6).on_volume(1)]);
+
+        assert!(files.search_by_name("d.txt", None).is_empty());
+        assert_eq!(vec![FileId::file(9).on_volume(1)], files.search_by_name("d2.txt", None));
+        assert_eq!(vec![FileId::file(8)], files.search_by_name("c_new.txt", None));
+        assert_eq!(vec![FileId::file(8).on_volume(2)], files.search_by_name("e_new.txt", None));
+        assert_eq!("e.txt", files.get_file(FileId::file(7).on_volume(2)).name);
+        assert_eq!("dir", files.get_file(FileId::directory(6).on_volume(1)).name);
+        assert_eq!(vec![FileId::file(9).on_volume(1)], files.children_of(FileId::directory(5).on_volume(1)));
     }
 
     #[test]
@@ -305,6 +564,201 @@ mod tests {
         assert_eq!(&"file0", &files.get_file(search.get(0).unwrap()).name);
     }
 
+    #[test]
+    fn every_link_is_found_with_its_own_path() {
+        let mut files = test_data();
+        let mut entry = new_file_record("linked");
+        entry.header.fr_number = 7;
+        entry.name_attrs[0].parent_id = 1;
+        let mut other_name = FilenameAttr::default();
+        other_name.name = "linked_too".to_string();
+        other_name.parent_id = 3;
+        entry.name_attrs.push(other_name);
+        files.add_file(FileEntity::links(entry));
+
+        let search = files.search_by_name("linked", None);
+        assert_eq!(2, search.len());
+        assert_eq!("dir1\\", files.path_of(files.get_file(search[0]).data).text);
+        assert_eq!("dir1\\dir2\\dir3\\", files.path_of(files.get_file(search[1]).data).text);
+    }
+
+    #[test]
+    fn update_removes_links_no_longer_in_record() {
+        let mut files = test_data();
+        let mut entry = new_file_record("linked");
+        entry.header.fr_number = 7;
+        let mut other_name = FilenameAttr::default();
+        other_name.name = "linked_too".to_string();
+        entry.name_attrs.push(other_name);
+        files.add_file(FileEntity::links(entry));
+
+        files.update_file(vec![new_file_with_parent("linked", 7, 1)]);
+
+        assert!(!files.get_file(FileId::file(7)).data.deleted());
+        assert!(files.get_file(FileId::file(7).with_link(1)).data.deleted());
+    }
+
+    #[test]
+    fn search_filters_on_link_kind() {
+        let mut files = test_data();
+        let mut junction = FileData::new(FileId::directory(4), FileId::directory(1), 0, DIR, false);
+        junction.set_link_kind(LinkKind::JUNCTION);
+        files.storage.upsert(junction, "dir4");
+
+        let search = files.search(&Query::parse("dir link:junction"));
+        assert_eq!(vec![FileId::directory(4)], search);
+        assert_eq!(5, files.search(&Query::parse("dir")).len());
+        assert!(files.search(&Query::parse("dir link:symlink")).is_empty());
+    }
+
+    #[test]
+    fn update_keeps_times_and_attributes() {
+        let mut files = test_data();
+        let mut entry = new_file_record("file0");
+        entry.standard_attr.modified = 1445836384;
+        entry.standard_attr.created = -5;
+        entry.standard_attr.accessed = 1545836384;
+        entry.standard_attr.dos_attributes = DosAttributes::HIDDEN | DosAttributes::ENCRYPTED;
+        files.update_file(FileEntity::links(entry));
+
+        let data = files.get_file(FileId::file(0)).data;
+        assert_eq!(1445836384, data.modified());
+        assert_eq!(0, data.created());
+        assert_eq!(1545836384, data.accessed());
+        assert_eq!(DosAttributes::HIDDEN | DosAttributes::ENCRYPTED, data.dos_attributes());
+    }
+
+    #[test]
+    fn streams_are_listed_when_enabled() {
+        let downloaded = || {
+            let mut entry = new_file_record("setup.exe");
+            entry.header.fr_number = 7;
+            entry.name_attrs[0].parent_id = 1;
+            entry.streams = vec![StreamAttr { name: "Zone.Identifier".to_string(), size: 26 }];
+            entry
+        };
+        let mut hidden = Files::new(1, false, Vec::new());
+        hidden.add_file(FileEntity::links(downloaded()));
+        let mut files = test_data();
+        files.add_file(FileEntity::links(downloaded()));
+
+        assert!(hidden.search(&Query::parse("Zone")).is_empty());
+        assert_eq!(vec![FileId::file(7)], hidden.search(&Query::parse("has:streams")));
+        let search = files.search(&Query::parse("Zone"));
+        assert_eq!(vec![FileId::stream(7)], search);
+        assert_eq!("setup.exe:Zone.Identifier", files.get_file(search[0]).name);
+        assert_eq!("dir1\\", files.path_of(files.get_file(search[0]).data).text);
+    }
+
+    #[test]
+    fn moved_directory_keeps_children_paths() {
+        let mut files = test_data();
+        let mut dir2 = new_file_record("moved");
+        dir2.header.flags = 0x02;
+        dir2.header.fr_number = 2;
+        dir2.name_attrs[0].parent_id = 0;
+        files.rename_file(FileEntity::links(dir2), FileId::directory(1), "dir2", FileId::directory(0), "moved");
+
+        assert!(files.search_by_name("dir2", None).is_empty());
+        let f = files.get_file(FileId::directory(3)).data;
+        assert_eq!("dir1\\dir0\\moved\\", files.path_of(f).text);
+    }
+
+    #[test]
+    fn moved_directory_takes_its_subtree() {
+        let mut files = test_data();
+        let mut dir2 = new_file_record("moved");
+        dir2.header.flags = 0x02;
+        dir2.header.fr_number = 2;
+        dir2.name_attrs[0].parent_id = 0;
+        files.rename_file(FileEntity::links(dir2), FileId::directory(1), "dir2", FileId::directory(0), "moved");
+
+        let mut moved = files.descendants_of(FileId::directory(0));
+        moved.sort();
+        assert_eq!(vec![FileId::directory(2), FileId::directory(3)], moved);
+        assert!(!files.children_of(FileId::directory(1)).contains(&FileId::directory(2)));
+    }
+
+    #[test]
+    fn cached_paths_follow_moved_directories() {
+        let mut files = test_data();
+        let f = files.get_file(FileId::directory(3)).data;
+        let cached = files.path_of(f);
+        assert!(Arc::ptr_eq(&cached, &files.path_of(files.get_file(FileId::directory(3)).data)));
+
+        let mut dir2 = new_file_record("moved");
+        dir2.header.flags = 0x02;
+        dir2.header.fr_number = 2;
+        dir2.name_attrs[0].parent_id = 0;
+        files.rename_file(FileEntity::links(dir2), FileId::directory(1), "dir2", FileId::directory(0), "moved");
+        assert_eq!("dir1\\dir0\\moved\\", files.path_of(files.get_file(FileId::directory(3)).data).text);
+
+        let mut dir0 = new_file_record("renamed");
+        dir0.header.flags = 0x02;
+        dir0.header.fr_number = 0;
+        dir0.name_attrs[0].parent_id = 1;
+        files.update_file(FileEntity::links(dir0));
+        assert_eq!("dir1\\renamed\\moved\\", files.path_of(files.get_file(FileId::directory(3)).data).text);
+    }
+
+    #[test]
+    fn search_by_path() {
+        let files = test_data();
+
+        let search = files.search(&Query::parse("dir path:dir2"));
+        assert_eq!(vec![FileId::directory(3)], search);
+        assert_eq!(6, files.search(&Query::parse("path:dir1")).len());
+    }
+
+    #[test]
+    fn rename_of_unknown_link_adds_it() {
+        let mut files = test_data();
+        files.rename_file(vec![new_file_with_parent("renamed", 7, 1)], FileId::directory(1), "old", FileId::directory(1), "renamed");
+
+        let search = files.search_by_name("renamed", None);
+        assert_eq!(vec![FileId::file(7)], search);
+    }
+
+    #[test]
+    fn scripted_changes_update_the_index() {
+        let mut files = test_data();
+        let script = "new\t7\t1\tfile\t42\treport.txt\nnew\t8\t2\tdir\t0\tarchive\n\n\
+            rename\t7\tfile\t1\treport.txt\t8\treport-2018.txt\ndelete\t0\t1\tfile\tfile0\n";
+        let mut source = ScriptedSource::parse(script).unwrap();
+        while let SourceEvent::Changes(changes) = source.next_event().unwrap() {
+            files.apply_changes(0, changes);
+        }
+
+        assert!(files.search_by_name("report.txt", None).is_empty());
+        let search = files.search_by_name("report-2018", None);
+        assert_eq!(vec![FileId::file(7)], search);
+        assert_eq!("dir1\\dir2\\archive\\", files.path_of(files.get_file(search[0]).data).text);
+        assert!(files.get_file(FileId::file(0)).data.deleted());
+    }
+
+    #[test]
+    fn deleted_files_are_not_found() {
+        let mut files = test_data();
+        files.delete_file(FileId::file(0));
+
+        assert!(files.search_by_name("file0", None).is_empty());
+        assert!(files.search(&Query::parse("file0")).is_empty());
+    }
+
+    #[test]
+    fn compaction_keeps_files_of_the_last_search() {
+        let mut files = test_data();
+        let search = files.search_by_name("file", None);
+        files.delete_file(search[0]);
+        for _ in 0..3 {
+            files.compact();
+        }
+
+        let item = files.get_file(search[0]);
+        assert!(item.data.deleted());
+        assert!(item.name.contains("file"));
+    }
+
     #[test]
     fn update_existing_file() {
         let mut files = test_data();
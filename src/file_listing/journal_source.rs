@@ -0,0 +1,59 @@
+use errors::MyErrorKind::UsnJournalError;
+use failure::Error;
+use file_listing::change_source::{
+    ChangeSource,
+    SourceEvent,
+};
+use ntfs::change_journal::JournalLoss;
+use ntfs::change_journal::UsnJournal;
+use ntfs::file_record::FixupPolicy;
+use ntfs::volumes::Volume;
+use rusqlite::Connection;
+use slog::Logger;
+use sql;
+
+/// The change journal of an NTFS volume, read from where the database left it. Every batch is
+/// written to the database before it is handed out, so a restart resumes right after it.
+pub struct JournalSource {
+    logger: Logger,
+    volume: Volume,
+    journal: UsnJournal,
+    conn: Connection,
+}
+
+impl JournalSource {
+    pub fn open(logger: &Logger, volume: Volume, fixup_policy: FixupPolicy) -> Result<Self, Error> {
+        let logger = logger.new(o!("volume" => volume.name.clone()));
+        let state = sql::load_journal_state(&volume).ok_or(UsnJournalError)?;
+        let journal = UsnJournal::new(volume.device_path()?, state, fixup_policy)?;
+        let conn = sql::main();
+        Ok(JournalSource { logger, volume, journal, conn })
+    }
+}
+
+impl ChangeSource for JournalSource {
+    fn next_event(&mut self) -> Result<SourceEvent, Error> {
+        match self.journal.get_new_changes() {
+            Ok((changes, report)) => {
+                if !report.torn_records.is_empty() {
+                    warn!(self.logger, "torn records in change journal"; "records" => format!("{:?}", report.torn_records));
+                }
+                for error in &report.errors {
+                    warn!(self.logger, "record of change journal skipped"; "error" => error.to_string());
+                }
+                if !report.unreadable.is_empty() {
+                    warn!(self.logger, "changed records could not be read"; "records" => format!("{:?}", report.unreadable));
+                }
+                sql::apply_changes(&mut self.conn, &self.volume, &changes, Some(self.journal.state()));
+                Ok(SourceEvent::Changes(changes))
+            }
+            Err(e) => match e.downcast::<JournalLoss>() {
+                Ok(loss) => Ok(SourceEvent::Lost(loss)),
+                Err(e) => match self.journal.loss() {
+                    Some(loss) => Ok(SourceEvent::Lost(loss)),
+                    None => Err(e),
+                },
+            },
+        }
+    }
+}
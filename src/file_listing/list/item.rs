@@ -1,4 +1,5 @@
 use file_listing::files::FileData;
+use file_listing::paths::DirPath;
 use twoway;
 use windows::utils::ToWide;
 
@@ -12,16 +13,20 @@ pub struct DisplayItem {
 }
 
 impl DisplayItem {
-    pub fn new(file: &FileData, name: String, path: String, query: &str) -> DisplayItem {
+    pub fn new(file: &FileData, name: String, path: &DirPath, target: Option<&str>, query: &str) -> DisplayItem {
         let matches = matches(query, &name);
         let size = if file.is_directory() {
             "".to_wide_null()
         } else {
             pretty_size(file.size()).to_wide_null()
         };
+        let path = match target {
+            Some(target) => format!("{} -> {}", path.text, target).to_wide_null(),
+            None => path.wide.clone(),
+        };
         DisplayItem {
             name,
-            path: path.to_wide_null(),
+            path,
             size,
             matches,
             flags: file.flags(),
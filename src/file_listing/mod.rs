@@ -1,49 +1,97 @@
 use crossbeam_channel as channel;
+use dir_walker;
 use dispatcher::UiAsyncMessage;
 use failure::Error;
+use file_listing::change_source::{
+    ChangeSource,
+    RescanSource,
+    SourceEvent,
+};
+use file_listing::file_entity::FileEntity;
 use file_listing::files::Files;
-use file_listing::FilesMsg::ChangeJournal;
+use file_listing::FilesMsg::{
+    ChangeJournal,
+    Compact,
+    JournalLost,
+    Reindexed,
+    RescanDue,
+};
+use file_listing::journal_source::JournalSource;
 use file_listing::list::item::DisplayItem;
 use file_listing::list::paint::ItemPaint;
+use file_listing::query::Query;
 use file_listing::state::FilesState;
 use gui::event::Event;
-use ntfs::change_journal;
+use ntfs::change_journal::JournalLoss;
 use ntfs::change_journal::UsnChange;
+use ntfs::file_record::FixupPolicy;
+use ntfs::parse_operation;
+use ntfs::volumes::{
+    Volume,
+    VolumeKind,
+};
 use plugin::CustomDrawResult;
 use plugin::DrawResult;
 use plugin::Plugin;
 use plugin::PluginState;
 use plugin::State;
 use slog::Logger;
+use sql;
 use std::sync::RwLock;
 use std::thread;
+use std::time::Duration;
 use std::time::Instant;
 
+mod journal_source;
 mod list;
+mod names;
+mod paths;
+mod query;
 mod storage;
 mod state;
+#[cfg(target_os = "linux")]
+mod watch_source;
+pub mod change_source;
 pub mod file_entity;
 pub mod files;
 
+// how often walked trees are indexed again when their changes can't be watched
+const RESCAN_INTERVAL_SECS: u64 = 15 * 60;
+// a step of compaction runs this often, a whole round takes three steps
+const COMPACT_INTERVAL_SECS: u64 = 20;
+
 pub struct FileListing(RwLock<Inner>);
 
 struct Inner {
     logger: Logger,
     files: Files,
     item_paint: ItemPaint,
+    volumes: Vec<Volume>,
+    fixup_policy: FixupPolicy,
+    sender: channel::Sender<UiAsyncMessage>,
 }
 
 unsafe impl Sync for Inner {}
 
 impl FileListing {
-    pub fn create(files: Files, sender: channel::Sender<UiAsyncMessage>, parent_logger: &Logger) -> Self {
+    /// `sources` feed the changes of `volumes`, in the same order.
+    pub fn create(files: Files, volumes: Vec<Volume>, sources: Vec<Box<ChangeSource>>, fixup_policy: FixupPolicy, sender: channel::Sender<UiAsyncMessage>, parent_logger: &Logger) -> Self {
         let logger = parent_logger.new(o!("type" =>"files"));
+        let memory = files.names_memory();
+        info!(logger, "names memory"; "names" => memory.names, "arena(bytes)" => memory.arena, "spans(bytes)" => memory.spans,
+            "lookup(bytes)" => memory.lookup, "total(bytes)" => memory.total(), "as strings(bytes)" => memory.as_strings);
         let item_paint = ItemPaint::create();
-        run_change_journal(sender).unwrap();
+        for (volume, source) in volumes.iter().zip(sources) {
+            run_change_source(volume, source, sender.clone(), logger.clone()).unwrap();
+        }
+        run_compaction(sender.clone()).unwrap();
         let inner = Inner {
             files,
             logger,
             item_paint,
+            volumes,
+            fixup_policy,
+            sender,
         };
         let res = RwLock::new(inner);
         FileListing(res)
@@ -51,25 +99,52 @@ impl FileListing {
 
     pub fn on_message(&self, msg: FilesMsg) {
         match msg {
-            ChangeJournal(changes) => self.update_files(changes),
+            ChangeJournal(volume, changes) => self.update_files(volume, changes),
+            JournalLost(volume, loss) => self.schedule_reindex(volume, &loss.to_string()),
+            RescanDue(volume) => self.schedule_reindex(volume, "changes can't be followed"),
+            Reindexed(volume, files, source) => self.replace_files(volume, files, source),
+            Compact => self.compact(),
         }
     }
 
-    fn update_files(&self, changes: Vec<UsnChange>) {
+    fn compact(&self) {
         let inner: &mut Inner = &mut *self.0.write().unwrap();
-        for change in changes {
-            match change {
-                UsnChange::DELETE(id) => inner.files.delete_file(id.into()),
-                UsnChange::UPDATE(file) => inner.files.update_file(file.into()),
-                UsnChange::NEW(file) => inner.files.add_file(file.into()),
-                UsnChange::IGNORE => {}
-            }
+        let now = Instant::now();
+        let compaction = inner.files.compact();
+        if compaction.files > 0 || compaction.names > 0 {
+            info!(inner.logger, "compaction"; "dropped files" => compaction.files, "dropped names" => compaction.names, "time(ms)" => millis_since(now));
         }
     }
+
+    /// The current index stays searchable while the volume is scanned again in the background.
+    fn schedule_reindex(&self, volume: u8, reason: &str) {
+        let inner = self.0.read().unwrap();
+        let volume = inner.volumes[volume as usize].clone();
+        warn!(inner.logger, "reindexing"; "volume" => volume.name.clone(), "reason" => reason);
+        run_reindex(volume, inner.fixup_policy, inner.sender.clone(), inner.logger.clone(), inner.files.show_streams()).unwrap();
+    }
+
+    fn replace_files(&self, volume: u8, files: Vec<FileEntity>, source: Box<ChangeSource>) {
+        let inner: &mut Inner = &mut *self.0.write().unwrap();
+        inner.files.replace_volume(volume, files);
+        let volume = &inner.volumes[volume as usize];
+        info!(inner.logger, "reindex finished"; "volume" => volume.name.clone());
+        run_change_source(volume, source, inner.sender.clone(), inner.logger.clone()).unwrap();
+    }
+
+    fn update_files(&self, volume: u8, changes: Vec<UsnChange>) {
+        let inner: &mut Inner = &mut *self.0.write().unwrap();
+        inner.files.apply_changes(volume, changes);
+    }
 }
 
+/// Messages from the threads following the volumes, tagged with the index of their volume.
 pub enum FilesMsg {
-    ChangeJournal(Vec<UsnChange>),
+    ChangeJournal(u8, Vec<UsnChange>),
+    JournalLost(u8, JournalLoss),
+    RescanDue(u8),
+    Reindexed(u8, Vec<FileEntity>, Box<ChangeSource>),
+    Compact,
 }
 
 impl Plugin for FileListing {
@@ -86,14 +161,15 @@ impl Plugin for FileListing {
     }
 
     fn prepare_item(&self, item_id: usize, state: &mut State) {
-        let query = state.query().to_string();
+        let query = Query::parse(state.query());
         let inner = self.0.read().unwrap();
         let plugin_state = state.plugin_state_mut::<FilesState>().unwrap();
         let file = plugin_state.file_in_current_search(item_id)
             .map(|file_id| inner.files.get_file(file_id))
             .unwrap();
         let path = inner.files.path_of(file.data);
-        plugin_state.item_cache_mut().insert(item_id as u32, DisplayItem::new(file.data, file.name.to_string(), path, &query));
+        let target = inner.files.link_target(file.data.id());
+        plugin_state.item_cache_mut().insert(item_id as u32, DisplayItem::new(file.data, file.name.to_string(), &path, target, &query.name));
     }
 
     fn handle_message(&self, msg: &str, _prev_state: &State) -> State {
@@ -103,7 +179,7 @@ impl Plugin for FileListing {
 //            if !inner.last_search.is_empty() && msg.starts_with(&inner.last_search) {
 //                inner.files.search_by_name(&msg, Some(&inner.items_current_search))
 //            } else {
-            inner.files.search_by_name(msg, None)
+            inner.files.search(&Query::parse(msg))
 //            }
         };
         let count = items.len();
@@ -122,14 +198,92 @@ fn millis_since(before: Instant) -> u32 {
     now.as_secs() as u32 * 1000 + now.subsec_millis()
 }
 
-pub fn run_change_journal(sender: channel::Sender<UiAsyncMessage>) -> Result<(), Error> {
-    thread::Builder::new().name("read journal".to_string()).spawn(move || {
-        let volume_path = "\\\\.\\C:";
-        let mut journal = change_journal::UsnJournal::new(volume_path).unwrap();
+/// Follows `source` on its own thread and hands its batches over to the `FileListing` of `volume`.
+pub fn run_change_source(volume: &Volume, mut source: Box<ChangeSource>, sender: channel::Sender<UiAsyncMessage>, logger: Logger) -> Result<(), Error> {
+    let index = volume.index;
+    let name = volume.name.clone();
+    thread::Builder::new().name(format!("read changes {}", name)).spawn(move || {
+        loop {
+            match source.next_event() {
+                Ok(SourceEvent::Changes(changes)) => sender.send(UiAsyncMessage::Files(FilesMsg::ChangeJournal(index, changes))),
+                Ok(SourceEvent::Lost(loss)) => {
+                    sender.send(UiAsyncMessage::Files(FilesMsg::JournalLost(index, loss)));
+                    return;
+                }
+                Ok(SourceEvent::Rescan) => {
+                    sender.send(UiAsyncMessage::Files(FilesMsg::RescanDue(index)));
+                    return;
+                }
+                Ok(SourceEvent::Finished) => return,
+                Err(e) => {
+                    warn!(logger, "read changes failed, retrying"; "volume" => name.clone(), "error" => e.to_string());
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Asks for a step of compaction every `COMPACT_INTERVAL_SECS`, it runs on the thread handling the messages.
+fn run_compaction(sender: channel::Sender<UiAsyncMessage>) -> Result<(), Error> {
+    thread::Builder::new().name("compaction".to_string()).spawn(move || {
         loop {
-            let changes = journal.get_new_changes().unwrap();
-            sender.send(UiAsyncMessage::Files(FilesMsg::ChangeJournal(changes)));
+            thread::sleep(Duration::from_secs(COMPACT_INTERVAL_SECS));
+            sender.send(UiAsyncMessage::Files(FilesMsg::Compact));
         }
     })?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The source following `volume` in a normal run. Walked trees have nothing to resume from, opening
+/// their source walks them again.
+pub fn change_source(logger: &Logger, volume: &Volume, fixup_policy: FixupPolicy) -> Result<Box<ChangeSource>, Error> {
+    match volume.kind {
+        VolumeKind::NTFS => journal_source(logger, volume, fixup_policy),
+        VolumeKind::DIRECTORY => directory_source(logger, volume),
+    }
+}
+
+/// A volume whose change journal can't be followed, e.g. because it is disabled, is scanned again now and then.
+fn journal_source(logger: &Logger, volume: &Volume, fixup_policy: FixupPolicy) -> Result<Box<ChangeSource>, Error> {
+    match JournalSource::open(logger, volume.clone(), fixup_policy) {
+        Ok(source) => Ok(Box::new(source)),
+        Err(e) => {
+            warn!(logger, "change journal can't be followed, rescanning periodically"; "volume" => volume.name.clone(), "error" => e.to_string());
+            Ok(Box::new(RescanSource::new(Duration::from_secs(RESCAN_INTERVAL_SECS))))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn directory_source(logger: &Logger, volume: &Volume) -> Result<Box<ChangeSource>, Error> {
+    watch_source::open(logger, sql::main(), volume, Duration::from_secs(RESCAN_INTERVAL_SECS))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn directory_source(logger: &Logger, volume: &Volume) -> Result<Box<ChangeSource>, Error> {
+    dir_walker::index(logger, &mut sql::main(), volume, &|_, _| {})?;
+    Ok(Box::new(RescanSource::new(Duration::from_secs(RESCAN_INTERVAL_SECS))))
+}
+
+/// Indexes `volume` again and opens the source following it from there on.
+fn reindex(logger: &Logger, volume: &Volume, fixup_policy: FixupPolicy) -> Result<Box<ChangeSource>, Error> {
+    if volume.kind == VolumeKind::NTFS {
+        parse_operation::reindex(logger, volume, fixup_policy)?;
+    }
+    change_source(logger, volume, fixup_policy)
+}
+
+/// Rescans `volume` and loads back what was found for it alone, the other volumes keep changing meanwhile.
+fn run_reindex(volume: Volume, fixup_policy: FixupPolicy, sender: channel::Sender<UiAsyncMessage>, logger: Logger, show_streams: bool) -> Result<(), Error> {
+    thread::Builder::new().name(format!("reindex {}", volume.name)).spawn(move || {
+        let reindexed = reindex(&logger, &volume, fixup_policy)
+            .and_then(|source| Ok((sql::load_volume(&volume, show_streams)?, source)));
+        match reindexed {
+            Ok((files, source)) => sender.send(UiAsyncMessage::Files(FilesMsg::Reindexed(volume.index, files, source))),
+            Err(e) => error!(logger, "reindex failed, the index is left as it was"; "volume" => volume.name.clone(), "error" => e.to_string()),
+        }
+    })?;
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use file_listing::files::NameId;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{
+    Hash,
+    Hasher,
+};
+use std::mem;
+
+// a String as held by a Vec<String>, the layout names had before the arena
+const STRING_HEADER: usize = 3 * 8;
+// heap blocks have an 8 byte header, come in 16 byte steps and are 32 bytes at least
+const BLOCK_HEADER: usize = 8;
+const BLOCK_STEP: usize = 16;
+const MIN_BLOCK: usize = 32;
+
+/// Every distinct file name of the index, stored once and packed one after the other in a single arena.
+/// A `NameId` stays valid as long as a file uses it, the ids of names no file uses anymore are given to
+/// new names. Names are at most 4GB in total.
+pub struct Names {
+    arena: String,
+    // the name `i` is `arena[spans[i].0..spans[i].1]`
+    spans: Vec<(u32, u32)>,
+    // how many files use each name
+    refs: Vec<u32>,
+    // a 32 bit hash keeps the table small, the few names that collide go to `collisions`
+    ids: HashMap<u32, NameId>,
+    // names whose hash was taken already by another name
+    collisions: HashMap<String, NameId>,
+    // names whose last file went away, freed by the next `free_unused`
+    unused: Vec<NameId>,
+    // ids free to be given to new names
+    free: Vec<NameId>,
+    // bytes of the arena no name uses anymore
+    garbage: usize,
+}
+
+/// How much memory the names take, next to what the same names took as a `Vec<String>`.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct NamesMemory {
+    pub names: usize,
+    pub arena: usize,
+    pub spans: usize,
+    pub lookup: usize,
+    /// An estimate, as every string was an allocation of its own.
+    pub as_strings: usize,
+}
+
+impl NamesMemory {
+    pub fn total(&self) -> usize {
+        self.arena + self.spans + self.lookup
+    }
+}
+
+impl Names {
+    pub fn new() -> Names {
+        Names {
+            arena: String::new(),
+            spans: Vec::new(),
+            refs: Vec::new(),
+            ids: HashMap::new(),
+            collisions: HashMap::new(),
+            unused: Vec::new(),
+            free: Vec::new(),
+            garbage: 0,
+        }
+    }
+
+    /// The id of `name` for one more file using it, the name is added if it is new.
+    pub fn intern(&mut self, name: &str) -> NameId {
+        let id = self.lookup(name);
+        self.refs[id.0 as usize] += 1;
+        id
+    }
+
+    fn lookup(&mut self, name: &str) -> NameId {
+        let hash = hash(name);
+        match self.ids.get(&hash).cloned() {
+            Some(id) if self.get(id) == name => id,
+            Some(_) => {
+                if let Some(id) = self.collisions.get(name) {
+                    return *id;
+                }
+                let id = self.push(name);
+                self.collisions.insert(name.to_string(), id);
+                id
+            }
+            None => {
+                let id = self.push(name);
+                self.ids.insert(hash, id);
+                id
+            }
+        }
+    }
+
+    fn push(&mut self, name: &str) -> NameId {
+        let start = self.arena.len() as u32;
+        self.arena.push_str(name);
+        let span = (start, self.arena.len() as u32);
+        match self.free.pop() {
+            Some(id) => {
+                self.spans[id.0 as usize] = span;
+                id
+            }
+            None => {
+                self.spans.push(span);
+                self.refs.push(0);
+                NameId(self.spans.len() as u32 - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, id: NameId) -> &str {
+        let (start, end) = self.spans[id.0 as usize];
+        &self.arena[start as usize..end as usize]
+    }
+
+    /// The names in use, or waiting for `free_unused`.
+    pub fn len(&self) -> usize {
+        self.spans.len() - self.free.len()
+    }
+
+    /// A file stopped using the name `id`. The name stays until `free_unused`, which leaves it if a file
+    /// took it again meanwhile.
+    pub fn release(&mut self, id: NameId) {
+        let refs = &mut self.refs[id.0 as usize];
+        *refs -= 1;
+        if *refs == 0 {
+            self.unused.push(id);
+        }
+    }
+
+    /// Frees the names no file uses anymore, so their ids go to new names. Only the names released since
+    /// the last call are looked at, the arena is packed again once half of it is garbage.
+    pub fn free_unused(&mut self) -> usize {
+        let mut unused = mem::replace(&mut self.unused, Vec::new());
+        unused.sort_unstable();
+        unused.dedup();
+        unused.retain(|id| self.refs[id.0 as usize] == 0);
+        for id in &unused {
+            self.forget(*id);
+        }
+        if self.garbage > 0 && self.garbage * 2 >= self.arena.len() {
+            self.pack();
+        }
+        unused.len()
+    }
+
+    fn forget(&mut self, id: NameId) {
+        let key = hash(self.get(id));
+        if self.ids.get(&key) == Some(&id) {
+            self.ids.remove(&key);
+            // a name that collided with this one takes its place in the table
+            let collided = self.collisions.keys().find(|name| hash(name) == key).cloned();
+            if let Some(name) = collided {
+                let other = self.collisions.remove(&name).unwrap();
+                self.ids.insert(key, other);
+            }
+        } else {
+            let name = self.get(id).to_string();
+            self.collisions.remove(&name);
+        }
+        let (start, end) = self.spans[id.0 as usize];
+        self.garbage += (end - start) as usize;
+        self.spans[id.0 as usize] = (0, 0);
+        self.free.push(id);
+    }
+
+    // the free ids have empty spans, so only names in use are copied
+    fn pack(&mut self) {
+        let mut arena = String::with_capacity(self.arena.len() - self.garbage);
+        for span in self.spans.iter_mut() {
+            let start = arena.len() as u32;
+            arena.push_str(&self.arena[span.0 as usize..span.1 as usize]);
+            *span = (start, arena.len() as u32);
+        }
+        self.arena = arena;
+        self.garbage = 0;
+    }
+
+    /// The ids of the names in use, in the order of their names. It is sorted on demand, as the ids
+    /// themselves follow the order names were added in.
+    pub fn sorted(&self) -> Vec<NameId> {
+        let mut free = vec![false; self.spans.len()];
+        for id in &self.free {
+            free[id.0 as usize] = true;
+        }
+        let mut ids = (0..self.spans.len() as u32)
+            .filter(|&id| !free[id as usize])
+            .map(NameId)
+            .collect::<Vec<NameId>>();
+        ids.sort_unstable_by(|a, b| self.get(*a).cmp(self.get(*b)));
+        ids
+    }
+
+    pub fn memory(&self) -> NamesMemory {
+        // a control byte per slot of the table
+        let entry = mem::size_of::<(u32, NameId)>() + 1;
+        let free = self.free.len() * (STRING_HEADER + heap_block(0));
+        let as_strings = (0..self.spans.len() as u32)
+            .map(|id| STRING_HEADER + heap_block(self.get(NameId(id)).len()))
+            .sum::<usize>() - free;
+        NamesMemory {
+            names: self.len(),
+            arena: self.arena.capacity(),
+            spans: self.spans.capacity() * mem::size_of::<(u32, u32)>() + self.refs.capacity() * mem::size_of::<u32>(),
+            lookup: self.ids.capacity() * entry + self.collisions.capacity() * (STRING_HEADER + entry),
+            as_strings,
+        }
+    }
+}
+
+fn hash(name: &str) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+fn heap_block(len: usize) -> usize {
+    ((len + BLOCK_HEADER + BLOCK_STEP - 1) / BLOCK_STEP * BLOCK_STEP).max(MIN_BLOCK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_gets_same_id() {
+        let mut names = Names::new();
+        let first = names.intern("file");
+        names.intern("other");
+
+        assert_eq!(first, names.intern("file"));
+        assert_eq!(2, names.len());
+    }
+
+    #[test]
+    fn ids_are_stable_when_lower_names_are_added() {
+        let mut names = Names::new();
+        let zeta = names.intern("zeta");
+        let beta = names.intern("beta");
+        names.intern("alpha");
+
+        assert_eq!("zeta", names.get(zeta));
+        assert_eq!("beta", names.get(beta));
+        assert_eq!(zeta, names.intern("zeta"));
+    }
+
+    #[test]
+    fn sorted_view_is_in_name_order() {
+        let mut names = Names::new();
+        for name in &["b", "d", "c", "a"] {
+            names.intern(name);
+        }
+        let d = names.intern("d");
+        names.release(d);
+        names.release(d);
+        names.free_unused();
+
+        let sorted = names.sorted().into_iter().map(|id| names.get(id)).collect::<Vec<&str>>();
+        assert_eq!(vec!["a", "b", "c"], sorted);
+    }
+
+    #[test]
+    fn names_are_packed_in_the_arena() {
+        let mut names = Names::new();
+        let empty = names.intern("");
+        let accents = names.intern("añejo café");
+        names.intern("file.txt");
+
+        assert_eq!("", names.get(empty));
+        assert_eq!("añejo café", names.get(accents));
+        assert_eq!("añejo café".len() + "file.txt".len(), names.arena.len());
+    }
+
+    #[test]
+    fn unused_names_are_freed_and_their_ids_reused() {
+        let mut names = Names::new();
+        let a = names.intern("a");
+        let b = names.intern("b");
+        let c = names.intern("c");
+
+        names.release(b);
+        assert_eq!(3, names.len());
+        assert_eq!(1, names.free_unused());
+        assert_eq!(2, names.len());
+        assert_eq!("a", names.get(a));
+        assert_eq!("c", names.get(c));
+
+        let d = names.intern("d");
+        assert_eq!(b, d);
+        assert_eq!("d", names.get(d));
+        assert_eq!(d, names.intern("d"));
+        assert_ne!(b, names.intern("b"));
+    }
+
+    #[test]
+    fn names_taken_again_are_not_freed() {
+        let mut names = Names::new();
+        let a = names.intern("a");
+        names.intern("b");
+
+        names.release(a);
+        assert_eq!(a, names.intern("a"));
+        names.release(a);
+        names.intern("a");
+        assert_eq!(0, names.free_unused());
+        assert_eq!("a", names.get(a));
+        assert_eq!(a, names.intern("a"));
+    }
+
+    #[test]
+    fn packing_keeps_the_ids() {
+        let mut names = Names::new();
+        let long = names.intern("a rather long name");
+        let short = names.intern("short");
+
+        names.release(long);
+        assert_eq!(1, names.free_unused());
+        assert_eq!("short".len(), names.arena.len());
+        assert_eq!("short", names.get(short));
+    }
+
+    #[test]
+    fn colliding_hashes_keep_names_apart() {
+        let mut names = Names::new();
+        let first = names.intern("first");
+        // pretend "second" hashes like "first"
+        names.ids.insert(hash("second"), first);
+
+        let second = names.intern("second");
+        assert_ne!(first, second);
+        assert_eq!("second", names.get(second));
+        assert_eq!(second, names.intern("second"));
+        assert_eq!(first, names.intern("first"));
+    }
+
+    #[test]
+    fn memory_report_compares_with_strings() {
+        let mut names = Names::new();
+        for i in 0..1000 {
+            names.intern(&format!("file {}.txt", i));
+        }
+        let memory = names.memory();
+
+        assert_eq!(1000, memory.names);
+        assert!(memory.arena >= names.arena.len());
+        assert_eq!(1000 * (STRING_HEADER + MIN_BLOCK), memory.as_strings);
+        assert!(memory.total() < memory.as_strings);
+    }
+}
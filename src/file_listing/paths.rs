@@ -0,0 +1,82 @@
+use file_listing::file_entity::FileId;
+use std::collections::HashMap;
+use std::iter;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+/// The path of a directory, ending with a separator, shared by every item shown from that directory.
+#[derive(Debug, Eq, PartialEq)]
+pub struct DirPath {
+    pub text: String,
+    /// `text` as the null terminated UTF-16 string the list draws.
+    pub wide: Vec<u16>,
+}
+
+impl DirPath {
+    pub fn new(text: String) -> DirPath {
+        let wide = text.encode_utf16().chain(iter::once(0)).collect();
+        DirPath { text, wide }
+    }
+}
+
+/// Paths of the directories met so far, by directory. They are built once and dropped when the directory
+/// or one above it is renamed, moved or deleted.
+#[derive(Default)]
+pub struct PathCache {
+    // items are prepared and searched with only a read lock on the files
+    paths: Mutex<HashMap<FileId, Arc<DirPath>>>,
+}
+
+impl PathCache {
+    pub fn get(&self, dir: FileId) -> Option<Arc<DirPath>> {
+        self.paths.lock().unwrap().get(&dir).cloned()
+    }
+
+    pub fn insert(&self, dir: FileId, path: DirPath) -> Arc<DirPath> {
+        let path = Arc::new(path);
+        self.paths.lock().unwrap().insert(dir, path.clone());
+        path
+    }
+
+    pub fn remove<I: IntoIterator<Item=FileId>>(&self, dirs: I) {
+        let mut paths = self.paths.lock().unwrap();
+        for dir in dirs {
+            paths.remove(&dir);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.paths.lock().unwrap().clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_path_is_null_terminated() {
+        let path = DirPath::new("C:\\añejo\\".to_string());
+        assert_eq!(10, path.wide.len());
+        assert_eq!(Some(&0), path.wide.last());
+        assert_eq!("C:\\añejo\\", String::from_utf16(&path.wide[..9]).unwrap());
+    }
+
+    #[test]
+    fn removed_paths_are_built_again() {
+        let cache = PathCache::default();
+        let first = cache.insert(FileId::directory(1), DirPath::new("a\\".to_string()));
+        cache.insert(FileId::directory(2), DirPath::new("a\\b\\".to_string()));
+
+        assert!(Arc::ptr_eq(&first, &cache.get(FileId::directory(1)).unwrap()));
+        cache.remove(vec![FileId::directory(1)]);
+        assert_eq!(None, cache.get(FileId::directory(1)));
+        assert_eq!("a\\b\\", cache.get(FileId::directory(2)).unwrap().text);
+    }
+}
@@ -0,0 +1,112 @@
+use file_listing::file_entity::LinkKind;
+use file_listing::files::FileData;
+use twoway;
+
+const LINK_FILTER: &str = "link:";
+const HAS_STREAMS_FILTER: &str = "has:streams";
+const PATH_FILTER: &str = "path:";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinkFilter {
+    Any,
+    Kind(LinkKind),
+}
+
+/// A search typed by the user: the part of the name to find plus optional `key:value` filters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Query {
+    pub name: String,
+    pub link: Option<LinkFilter>,
+    pub has_streams: bool,
+    /// Part of the path of the directory holding the file.
+    pub path: Option<String>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Query {
+        let mut link = None;
+        let mut has_streams = false;
+        let mut path = None;
+        let mut name_parts = Vec::new();
+        for part in input.split(' ') {
+            if part == HAS_STREAMS_FILTER {
+                has_streams = true;
+                continue;
+            }
+            if part.starts_with(PATH_FILTER) && part.len() > PATH_FILTER.len() {
+                path = Some(part[PATH_FILTER.len()..].to_string());
+                continue;
+            }
+            match link_filter(part) {
+                Some(filter) => link = Some(filter),
+                None => name_parts.push(part),
+            }
+        }
+        Query { name: name_parts.join(" ").trim().to_string(), link, has_streams, path }
+    }
+
+    pub fn matches(&self, name: &str, data: &FileData) -> bool {
+        let link_matches = match self.link {
+            None => true,
+            Some(LinkFilter::Any) => data.link_kind() != LinkKind::NONE,
+            Some(LinkFilter::Kind(kind)) => data.link_kind() == kind,
+        };
+        let streams_match = !self.has_streams || data.has_streams();
+        link_matches && streams_match && twoway::find_str(name, &self.name).is_some()
+    }
+
+    /// Whether `path`, the path of the directory holding a file, passes the path filter.
+    pub fn matches_path(&self, path: &str) -> bool {
+        self.path.as_ref().map_or(true, |filter| twoway::find_str(path, filter).is_some())
+    }
+}
+
+fn link_filter(part: &str) -> Option<LinkFilter> {
+    if !part.starts_with(LINK_FILTER) {
+        return None;
+    }
+    match &part[LINK_FILTER.len()..] {
+        "" | "any" => Some(LinkFilter::Any),
+        "symlink" => Some(LinkFilter::Kind(LinkKind::SYMLINK)),
+        "junction" => Some(LinkFilter::Kind(LinkKind::JUNCTION)),
+        "wsl" => Some(LinkFilter::Kind(LinkKind::WSL)),
+        "other" => Some(LinkFilter::Kind(LinkKind::OTHER)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_query_is_a_name() {
+        let query = Query::parse("my file");
+        assert_eq!("my file", query.name);
+        assert_eq!(None, query.link);
+        assert!(!query.has_streams);
+        assert_eq!(None, query.path);
+    }
+
+    #[test]
+    fn link_filter_is_removed_from_name() {
+        assert_eq!(Query { name: "docs".to_string(), link: Some(LinkFilter::Kind(LinkKind::JUNCTION)), has_streams: false, path: None }, Query::parse("docs link:junction"));
+        assert_eq!(Query { name: "".to_string(), link: Some(LinkFilter::Any), has_streams: false, path: None }, Query::parse("link:"));
+        assert_eq!(Query { name: "link:unknown".to_string(), link: None, has_streams: false, path: None }, Query::parse("link:unknown"));
+    }
+
+    #[test]
+    fn has_streams_filter_is_removed_from_name() {
+        assert_eq!(Query { name: ".exe".to_string(), link: None, has_streams: true, path: None }, Query::parse("has:streams .exe"));
+    }
+
+    #[test]
+    fn path_filter_is_removed_from_name() {
+        let query = Query::parse("report path:docs\\old");
+        assert_eq!("report", query.name);
+        assert_eq!(Some("docs\\old".to_string()), query.path);
+        assert!(query.matches_path("C:\\docs\\old\\"));
+        assert!(!query.matches_path("C:\\docs\\"));
+        assert_eq!("path:", Query::parse("path:").name);
+    }
+}
@@ -2,88 +2,123 @@ use file_listing::file_entity::FileEntity;
 use file_listing::file_entity::FileId;
 use file_listing::file_entity::FileType;
 use file_listing::files::FileData;
-use file_listing::files::NameId;
+use file_listing::names::{
+    Names,
+    NamesMemory,
+};
 use std::borrow::Borrow;
-use std::collections::BTreeSet;
-use std::collections::HashMap;
+use std::cmp::Ordering::{
+    Greater,
+    Less,
+};
+use std::collections::{
+    HashMap,
+    HashSet,
+};
 use std::iter::Chain;
 use std::iter::Iterator;
 use std::mem;
+use std::ops::Range;
 use std::slice::Iter;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+/// Which part of the storage the next call to `compact` reclaims.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CompactStep {
+    DIRS,
+    FILES,
+    NAMES,
+}
+
+/// What a call to `compact` dropped.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Compaction {
+    pub files: usize,
+    pub names: usize,
+}
 
 pub struct Storage {
     file_data: Vec<FileData>,
     dir_data: Vec<FileData>,
-    names: Vec<String>,
+    names: Names,
+    link_targets: HashMap<FileId, String>,
+    // the live links and streams in each directory, the root being its own parent is left out
+    children: HashMap<FileId, Vec<FileId>>,
+    // bumped by every search, see `seal`
+    generation: AtomicUsize,
+    // the generation each recent tombstone was made in, tombstones missing here are older than any search
+    deleted_at: HashMap<FileId, usize>,
+    next_step: CompactStep,
 }
 
 impl Storage {
     pub fn new() -> Storage {
         let file_data = Vec::new();
         let dir_data = Vec::new();
-        let names = Vec::new();
+        let names = Names::new();
+        let link_targets = HashMap::new();
         Storage {
             file_data,
             dir_data,
             names,
+            link_targets,
+            children: HashMap::new(),
+            generation: AtomicUsize::new(0),
+            deleted_at: HashMap::new(),
+            next_step: CompactStep::DIRS,
         }
     }
 
     pub fn bulk_insert(&mut self, files: Vec<FileEntity>) {
-        let names = files.iter().map(|f| f.name().to_string()).collect::<BTreeSet<String>>();
-        {
-            let mut names_idx: HashMap<&str, u32> = HashMap::new();
-
-            for (pos, name) in names.iter().enumerate() {
-                names_idx.insert(name, pos as u32);
-            }
-            let mut files = files.into_iter()
-                .map(|f| {
-                    let name_id = names_idx.get(f.name()).unwrap();
-                    let mut data: FileData = f.into();
-                    data.set_name_id(NameId(*name_id));
-                    data
-                })
-                .collect::<Vec<FileData>>();
-            files.sort_unstable_by_key(|f| f.id());
-            for f in files {
-                if f.is_directory() {
-                    self.dir_data.push(f);
-                } else {
-                    self.file_data.push(f);
-                }
-            }
-        }
-
-        mem::replace(&mut self.names, names.into_iter().collect());
+        let (dirs, files) = self.prepare(files);
+        self.dir_data.extend(dirs);
+        self.file_data.extend(files);
     }
 
-    fn update_file_name_ids(&mut self, new_name_id: NameId) {
-        self.file_data.iter_mut()
-            .chain(self.dir_data.iter_mut())
-            .filter(|f| f.name_id().0 >= new_name_id.0)
-            .for_each(|f| {
-                let old = f.name_id().0;
-                f.set_name_id(NameId(old + 1));
-            })
+    /// Swaps what is stored for `volume` with `files`, the other volumes are left as they are.
+    pub fn replace_volume(&mut self, volume: u8, files: Vec<FileEntity>) {
+        self.children.retain(|dir, _| dir.volume() != volume);
+        self.link_targets.retain(|id, _| id.volume() != volume);
+        self.deleted_at.retain(|id, _| id.volume() != volume);
+        let (dirs, files) = self.prepare(files);
+        splice_volume(&mut self.dir_data, &mut self.names, volume, dirs);
+        splice_volume(&mut self.file_data, &mut self.names, volume, files);
     }
 
-    fn upsert_name<T: Into<String>>(&mut self, name: T) -> NameId {
-        let name = name.into();
-        match self.names.binary_search(&name) {
-            Ok(pos) => NameId(pos as u32),
-            Err(pos) => {
-                self.names.insert(pos, name);
-                let new = NameId(pos as u32);
-                self.update_file_name_ids(new);
-                new
+    /// Interns the names of `files` and indexes them by directory, returning the directories and the
+    /// files apart, each sorted by id.
+    fn prepare(&mut self, files: Vec<FileEntity>) -> (Vec<FileData>, Vec<FileData>) {
+        for f in &files {
+            if let Some(target) = f.link_target() {
+                self.link_targets.insert(f.id(), target.to_string());
             }
         }
+        let names = &mut self.names;
+        let mut files = files.into_iter()
+            .map(|f| {
+                let name_id = names.intern(f.name());
+                let mut data: FileData = f.into();
+                data.set_name_id(name_id);
+                data
+            })
+            .collect::<Vec<FileData>>();
+        files.sort_unstable_by_key(|f| f.id());
+        for f in &files {
+            add_child(&mut self.children, f);
+        }
+        files.into_iter().partition(|f| f.is_directory())
     }
 
     pub fn upsert<T: Into<String>>(&mut self, mut data: FileData, name: T) {
-        let new_name_id = self.upsert_name(name);
+        let new_name_id = self.names.intern(&name.into());
         data.set_name_id(new_name_id);
+        if data.deleted() {
+            self.deleted_at.insert(data.id(), self.generation.load(Ordering::SeqCst));
+        }
+        let children = &mut self.children;
         let files = match data.is_directory() {
             true => &mut self.dir_data,
             false => &mut self.file_data,
@@ -91,9 +126,15 @@ impl Storage {
         match files.binary_search_by_key(&data.id(), |f| f.id()) {
             Ok(pos) => {
                 let old_data = files.get_mut(pos).unwrap();
+                if !old_data.deleted() {
+                    remove_child(children, old_data);
+                }
+                self.names.release(old_data.name_id());
+                add_child(children, &data);
                 mem::replace(old_data, data);
             }
             Err(pos) => {
+                add_child(children, &data);
                 files.insert(pos, data)
             }
         };
@@ -102,26 +143,214 @@ impl Storage {
     pub fn delete<T: Borrow<FileId>>(&mut self, id: T) {
         let files = match id.borrow().f_type() {
             FileType::DIRECTORY => &mut self.dir_data,
-            FileType::FILE => &mut self.file_data,
+            FileType::FILE | FileType::STREAM => &mut self.file_data,
         };
         match files.binary_search_by_key(id.borrow(), |f| f.id()) {
             Err(_) => println!("Delete file\tNot found\t{:?}", id.borrow()),
-            Ok(pos) => files.get_mut(pos).unwrap().set_deleted(true),
+            Ok(pos) => {
+                remove_child(&mut self.children, &files[pos]);
+                files.get_mut(pos).unwrap().set_deleted(true);
+                self.deleted_at.insert(*id.borrow(), self.generation.load(Ordering::SeqCst));
+            }
+        }
+    }
+
+    pub fn set_link_target<T: Borrow<FileId>>(&mut self, id: T, target: Option<&str>) {
+        match target {
+            Some(target) => self.link_targets.insert(*id.borrow(), target.to_string()),
+            None => self.link_targets.remove(id.borrow()),
+        };
+    }
+
+    pub fn link_target<T: Borrow<FileId>>(&self, id: T) -> Option<&str> {
+        self.link_targets.get(id.borrow()).map(String::as_str)
+    }
+
+    /// Marks as deleted the link of the record `id` found under `parent_id` with `name`.
+    /// Returns false when there is no such link.
+    pub fn delete_link<T: Borrow<FileId>>(&mut self, id: T, parent_id: FileId, name: &str) -> bool {
+        let names = &self.names;
+        let files = match id.borrow().f_type() {
+            FileType::DIRECTORY => &mut self.dir_data,
+            FileType::FILE | FileType::STREAM => &mut self.file_data,
+        };
+        let range = link_range(files, id.borrow());
+        let f_type = id.borrow().f_type();
+        let link = files[range].iter_mut()
+            .filter(|f| f.id().f_type() == f_type && !f.deleted())
+            .find(|f| f.parent_id() == parent_id && names.get(f.name_id()) == name);
+        match link {
+            None => false,
+            Some(data) => {
+                remove_child(&mut self.children, data);
+                data.set_deleted(true);
+                self.deleted_at.insert(data.id(), self.generation.load(Ordering::SeqCst));
+                true
+            }
+        }
+    }
+
+    /// Moves the link of the record `id` found under `old_parent` with `old_name`, keeping its position.
+    /// Returns false when there is no such link.
+    pub fn rename_link<T: Borrow<FileId>>(&mut self, id: T, old_parent: FileId, old_name: &str, new_parent: FileId, new_name: &str) -> bool {
+        let names = &mut self.names;
+        let files = match id.borrow().f_type() {
+            FileType::DIRECTORY => &mut self.dir_data,
+            FileType::FILE | FileType::STREAM => &mut self.file_data,
+        };
+        let range = link_range(files, id.borrow());
+        let f_type = id.borrow().f_type();
+        let link = files[range].iter_mut()
+            .filter(|f| f.id().f_type() == f_type && !f.deleted())
+            .find(|f| f.parent_id() == old_parent && names.get(f.name_id()) == old_name);
+        match link {
+            None => false,
+            Some(data) => {
+                remove_child(&mut self.children, data);
+                let new_name_id = names.intern(new_name);
+                names.release(data.name_id());
+                data.set_parent_id(new_parent);
+                data.set_name_id(new_name_id);
+                add_child(&mut self.children, data);
+                true
+            }
         }
     }
 
+    /// Marks as deleted the links of the record `id` numbered `count` and above.
+    pub fn truncate_links<T: Borrow<FileId>>(&mut self, id: T, count: u16) {
+        let files = match id.borrow().f_type() {
+            FileType::DIRECTORY => &mut self.dir_data,
+            FileType::FILE | FileType::STREAM => &mut self.file_data,
+        };
+        let range = link_range(files, id.borrow());
+        let f_type = id.borrow().f_type();
+        let generation = self.generation.load(Ordering::SeqCst);
+        let deleted_at = &mut self.deleted_at;
+        let children = &mut self.children;
+        files[range].iter_mut()
+            .filter(|f| f.id().f_type() == f_type && f.id().link() >= count && !f.deleted())
+            .for_each(|f| {
+                remove_child(children, f);
+                f.set_deleted(true);
+                deleted_at.insert(f.id(), generation);
+            });
+    }
+
     pub fn get<T: Borrow<FileId>>(&self, id: T) -> StorageItem {
+        self.find(id).unwrap()
+    }
+
+    /// None when there is no such file, or its tombstone was dropped already.
+    pub fn find<T: Borrow<FileId>>(&self, id: T) -> Option<StorageItem> {
         let files = match id.borrow().f_type() {
             FileType::DIRECTORY => &self.dir_data,
-            FileType::FILE => &self.file_data,
+            FileType::FILE | FileType::STREAM => &self.file_data,
         };
-        let pos = files.binary_search_by_key(id.borrow(), |f| f.id()).unwrap();
-        let data = files.get(pos).unwrap();
-        let name = self.names.get(data.name_id().0 as usize).unwrap();
-        StorageItem {
+        let pos = files.binary_search_by_key(id.borrow(), |f| f.id()).ok()?;
+        let data = &files[pos];
+        let name = self.names.get(data.name_id());
+        Some(StorageItem {
             data,
             name,
+        })
+    }
+
+    /// The links and streams directly in the directory `dir`, deleted ones left out. In no particular order.
+    pub fn children_of<T: Borrow<FileId>>(&self, dir: T) -> &[FileId] {
+        self.children.get(dir.borrow()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Everything below the directory `dir`, at any depth. A directory is listed once even when
+    /// inconsistent changes made it its own ancestor.
+    pub fn descendants_of<T: Borrow<FileId>>(&self, dir: T) -> Vec<FileId> {
+        let mut descendants = Vec::new();
+        let mut visited = HashSet::new();
+        visited.insert(*dir.borrow());
+        let mut pending = vec![*dir.borrow()];
+        while let Some(dir) = pending.pop() {
+            for child in self.children_of(dir) {
+                if child.f_type() == FileType::DIRECTORY {
+                    if !visited.insert(child.with_link(0)) {
+                        continue;
+                    }
+                    pending.push(child.with_link(0));
+                }
+                descendants.push(*child);
+            }
+        }
+        descendants
+    }
+
+    /// The directories above `file`, from its parent up to the root. Stops early when a parent is missing.
+    pub fn ancestors_of<T: Borrow<FileId>>(&self, file: T) -> Vec<StorageItem> {
+        let mut ancestors = Vec::new();
+        let mut current = match self.find(file) {
+            Some(item) => item.data,
+            None => return ancestors,
+        };
+        while !current.is_root() {
+            match self.find(current.parent_id()) {
+                Some(item) => {
+                    current = item.data;
+                    ancestors.push(item);
+                }
+                None => break,
+            }
+        }
+        ancestors
+    }
+
+    /// Called when a search starts. It skips tombstones, so those made before it can't be shown
+    /// anymore and `compact` is free to drop them.
+    pub fn seal(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Reclaims one part of the storage per call, so the index is never locked for long: the tombstones
+    /// of directories, then those of files, then the names nothing refers to anymore.
+    pub fn compact(&mut self) -> Compaction {
+        let step = self.next_step;
+        self.next_step = match step {
+            CompactStep::DIRS => CompactStep::FILES,
+            CompactStep::FILES => CompactStep::NAMES,
+            CompactStep::NAMES => CompactStep::DIRS,
+        };
+        match step {
+            CompactStep::DIRS => Compaction { files: self.drop_tombstones(FileType::DIRECTORY), names: 0 },
+            CompactStep::FILES => Compaction { files: self.drop_tombstones(FileType::FILE), names: 0 },
+            CompactStep::NAMES => Compaction { files: 0, names: self.names.free_unused() },
+        }
+    }
+
+    fn drop_tombstones(&mut self, f_type: FileType) -> usize {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let deleted_at = &self.deleted_at;
+        let link_targets = &mut self.link_targets;
+        let names = &mut self.names;
+        let files = match f_type {
+            FileType::DIRECTORY => &mut self.dir_data,
+            FileType::FILE | FileType::STREAM => &mut self.file_data,
+        };
+        let before = files.len();
+        files.retain(|f| {
+            let reclaim = f.deleted() && deleted_at.get(&f.id()).map_or(true, |at| *at < generation);
+            if reclaim {
+                link_targets.remove(&f.id());
+                names.release(f.name_id());
+            }
+            !reclaim
+        });
+        if files.len() < files.capacity() / 2 {
+            files.shrink_to_fit();
         }
+        let dropped = before - files.len();
+        self.deleted_at.retain(|_, at| *at >= generation);
+        dropped
+    }
+
+    pub fn names_memory(&self) -> NamesMemory {
+        self.names.memory()
     }
 
     pub fn iter(&self) -> StorageIter {
@@ -135,13 +364,59 @@ impl Storage {
     }
 }
 
+/// Adds `data` to the children of its parent, unless it is deleted or the root.
+fn add_child(children: &mut HashMap<FileId, Vec<FileId>>, data: &FileData) {
+    if !data.deleted() && !data.is_root() {
+        children.entry(data.parent_id()).or_insert_with(Vec::new).push(data.id());
+    }
+}
+
+fn remove_child(children: &mut HashMap<FileId, Vec<FileId>>, data: &FileData) {
+    let now_empty = match children.get_mut(&data.parent_id()) {
+        Some(siblings) => {
+            if let Some(pos) = siblings.iter().position(|id| *id == data.id()) {
+                siblings.swap_remove(pos);
+            }
+            siblings.is_empty()
+        }
+        None => false,
+    };
+    if now_empty {
+        children.remove(&data.parent_id());
+    }
+}
+
+/// Ids start with their volume, so the entries of `volume` are the ones between those of the volumes around it.
+fn splice_volume(files: &mut Vec<FileData>, names: &mut Names, volume: u8, replacement: Vec<FileData>) {
+    let start = volume_start(files, volume);
+    let end = volume.checked_add(1).map_or(files.len(), |next| volume_start(files, next));
+    for old in files.splice(start..end, replacement) {
+        names.release(old.name_id());
+    }
+}
+
+fn volume_start(files: &[FileData], volume: u8) -> usize {
+    match files.binary_search_by(|f| if f.id().volume() < volume { Less } else { Greater }) {
+        Ok(pos) | Err(pos) => pos,
+    }
+}
+
+/// Positions of all the links and streams of the record `id`, which are kept next to each other.
+fn link_range(files: &[FileData], id: &FileId) -> Range<usize> {
+    let start = match files.binary_search_by_key(&id.with_link(0), |f| f.id()) {
+        Ok(pos) | Err(pos) => pos,
+    };
+    let count = files[start..].iter().take_while(|f| f.id().same_record(id)).count();
+    start..start + count
+}
+
 pub struct StorageItem<'a> {
     pub name: &'a str,
     pub data: &'a FileData,
 }
 
 pub struct StorageIter<'a> {
-    names: &'a [String],
+    names: &'a Names,
     inner: Chain<Iter<'a, FileData>, Iter<'a, FileData>>,
 }
 
@@ -151,7 +426,7 @@ impl<'a> Iterator for StorageIter<'a> {
     fn next(&mut self) -> Option<StorageItem<'a>> {
         self.inner.next().map(|data| {
             StorageItem {
-                name: self.names.get(data.name_id().0 as usize).unwrap(),
+                name: self.names.get(data.name_id()),
                 data,
             }
         })
@@ -330,6 +605,203 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
-    fn old_names_are_removed() {}
+    fn new_name_keeps_name_ids_of_existing_files() {
+        let mut storage = test_data();
+        let name_id = storage.get(FileId::file(2)).data.name_id();
+
+        storage.upsert(FileData::new(FileId::file(4), FileId::directory(1), 0, FILE, false), "aaa_file");
+
+        assert_eq!(name_id, storage.get(FileId::file(2)).data.name_id());
+        assert_eq!("file2", storage.get(FileId::file(2)).name);
+    }
+
+    #[test]
+    fn links_of_a_file_are_stored_separately() {
+        let mut storage = test_data();
+        let link = FileData::new(FileId::file(1).with_link(1), FileId::directory(2), 0, FILE, false);
+        storage.upsert(link, "file1_link");
+
+        assert_eq!("file1", storage.get(FileId::file(1)).name);
+        assert_eq!("file1_link", storage.get(FileId::file(1).with_link(1)).name);
+        assert_eq!(FileId::directory(2), storage.get(FileId::file(1).with_link(1)).data.parent_id());
+        assert_eq!("file2", storage.get(FileId::file(2)).name);
+    }
+
+    #[test]
+    fn delete_only_matching_link() {
+        let mut storage = test_data();
+        let link = FileData::new(FileId::file(1).with_link(1), FileId::directory(2), 0, FILE, false);
+        storage.upsert(link, "file1_link");
+
+        assert!(storage.delete_link(FileId::file(1), FileId::directory(2), "file1_link"));
+
+        assert!(!storage.get(FileId::file(1)).data.deleted());
+        assert!(storage.get(FileId::file(1).with_link(1)).data.deleted());
+        assert!(!storage.get(FileId::file(2)).data.deleted());
+        assert!(!storage.delete_link(FileId::file(1), FileId::directory(2), "file1_link"));
+        assert!(!storage.delete_link(FileId::file(2), FileId::directory(2), "file2"));
+    }
+
+    #[test]
+    fn truncate_links_keeps_lower_links() {
+        let mut storage = test_data();
+        let link = FileData::new(FileId::file(1).with_link(1), FileId::directory(2), 0, FILE, false);
+        storage.upsert(link, "file1_link");
+
+        storage.truncate_links(FileId::file(1), 1);
+
+        assert!(!storage.get(FileId::file(1)).data.deleted());
+        assert!(storage.get(FileId::file(1).with_link(1)).data.deleted());
+    }
+
+    fn sorted(ids: &[FileId]) -> Vec<FileId> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn children_are_listed_by_directory() {
+        let storage = test_data();
+
+        assert_eq!(vec![FileId::directory(0), FileId::file(0), FileId::file(1), FileId::directory(2), FileId::file(2)],
+                   sorted(storage.children_of(FileId::directory(1))));
+        assert_eq!(vec![FileId::directory(3)], sorted(storage.children_of(FileId::directory(2))));
+        assert!(storage.children_of(FileId::directory(3)).is_empty());
+    }
+
+    #[test]
+    fn deleted_files_leave_their_directory() {
+        let mut storage = test_data();
+        let link = FileData::new(FileId::file(1).with_link(1), FileId::directory(2), 0, FILE, false);
+        storage.upsert(link, "file1_link");
+
+        storage.delete(FileId::file(0));
+        storage.delete_link(FileId::file(1), FileId::directory(2), "file1_link");
+        storage.delete(FileId::directory(3));
+
+        assert_eq!(vec![FileId::directory(0), FileId::file(1), FileId::directory(2), FileId::file(2)],
+                   sorted(storage.children_of(FileId::directory(1))));
+        assert!(storage.children_of(FileId::directory(2)).is_empty());
+    }
+
+    #[test]
+    fn moved_files_change_directory() {
+        let mut storage = test_data();
+
+        assert!(storage.rename_link(FileId::file(0), FileId::directory(1), "file0", FileId::directory(3), "file0"));
+        storage.upsert(FileData::new(FileId::file(1), FileId::directory(3), 0, FILE, false), "file1");
+
+        assert_eq!(vec![FileId::file(0), FileId::file(1)], sorted(storage.children_of(FileId::directory(3))));
+        assert_eq!(vec![FileId::directory(0), FileId::directory(2), FileId::file(2)],
+                   sorted(storage.children_of(FileId::directory(1))));
+    }
+
+    #[test]
+    fn descendants_follow_moved_directories() {
+        let mut storage = test_data();
+        storage.upsert(FileData::new(FileId::file(4), FileId::directory(3), 0, FILE, false), "file4");
+
+        assert_eq!(vec![FileId::directory(3), FileId::file(4)], sorted(&storage.descendants_of(FileId::directory(2))));
+
+        storage.rename_link(FileId::directory(3), FileId::directory(2), "dir3", FileId::directory(0), "dir3");
+        assert!(storage.descendants_of(FileId::directory(2)).is_empty());
+        assert_eq!(vec![FileId::directory(3), FileId::file(4)], sorted(&storage.descendants_of(FileId::directory(0))));
+        assert_eq!(7, storage.descendants_of(FileId::directory(1)).len());
+    }
+
+    #[test]
+    fn descendants_stop_at_a_cycle() {
+        let mut storage = test_data();
+        // dir2 ends up below dir3, which is in dir2
+        storage.rename_link(FileId::directory(2), FileId::directory(1), "dir2", FileId::directory(3), "dir2");
+
+        assert_eq!(vec![FileId::directory(3)], storage.descendants_of(FileId::directory(2)));
+        assert_eq!(vec![FileId::directory(2)], storage.descendants_of(FileId::directory(3)));
+        assert_eq!(vec![FileId::directory(0), FileId::file(0), FileId::file(1), FileId::file(2)],
+                   sorted(&storage.descendants_of(FileId::directory(1))));
+    }
+
+    #[test]
+    fn ancestors_go_up_to_the_root() {
+        let storage = test_data();
+
+        let ancestors = storage.ancestors_of(FileId::directory(3)).into_iter().map(|i| i.name).collect::<Vec<&str>>();
+        assert_eq!(vec!["dir2", "dir1"], ancestors);
+        assert!(storage.ancestors_of(FileId::directory(1)).is_empty());
+        assert!(storage.ancestors_of(FileId::file(9)).is_empty());
+    }
+
+    fn compact_all(storage: &mut Storage) -> Compaction {
+        let mut total = Compaction::default();
+        for _ in 0..3 {
+            let step = storage.compact();
+            total.files += step.files;
+            total.names += step.names;
+        }
+        total
+    }
+
+    #[test]
+    fn old_names_are_removed() {
+        let mut storage = test_data();
+        let prev_name_len = storage.names.len();
+
+        storage.upsert(FileData::new(FileId::file(1), FileId::directory(1), 25, FILE, false), "file_update");
+        assert_eq!(prev_name_len + 1, storage.names.len());
+
+        assert_eq!(Compaction { files: 0, names: 1 }, compact_all(&mut storage));
+        assert_eq!(prev_name_len, storage.names.len());
+        assert_eq!("file_update", storage.get(FileId::file(1)).name);
+        assert_eq!("file2", storage.get(FileId::file(2)).name);
+        assert_eq!("dir3", storage.get(FileId::directory(3)).name);
+    }
+
+    #[test]
+    fn compaction_keeps_name_ids() {
+        let mut storage = test_data();
+        let file1 = storage.get(FileId::file(1)).data.name_id();
+        let file2 = storage.get(FileId::file(2)).data.name_id();
+        let dir3 = storage.get(FileId::directory(3)).data.name_id();
+
+        assert!(storage.rename_link(FileId::file(1), FileId::directory(1), "file1", FileId::directory(1), "renamed"));
+        assert_eq!(1, compact_all(&mut storage).names);
+        assert_eq!(file2, storage.get(FileId::file(2)).data.name_id());
+        assert_eq!(dir3, storage.get(FileId::directory(3)).data.name_id());
+
+        // the freed id goes to the next new name
+        storage.upsert(FileData::new(FileId::file(4), FileId::directory(1), 0, FILE, false), "new");
+        assert_eq!(file1, storage.get(FileId::file(4)).data.name_id());
+        assert_eq!("renamed", storage.get(FileId::file(1)).name);
+        assert_eq!("file2", storage.get(FileId::file(2)).name);
+    }
+
+    #[test]
+    fn tombstones_are_kept_until_a_search_skips_them() {
+        let mut storage = test_data();
+        storage.delete(FileId::file(1));
+        storage.delete(FileId::directory(3));
+
+        assert_eq!(Compaction::default(), compact_all(&mut storage));
+        assert!(storage.get(FileId::file(1)).data.deleted());
+
+        storage.seal();
+        assert_eq!(Compaction { files: 2, names: 2 }, compact_all(&mut storage));
+        assert!(storage.find(FileId::file(1)).is_none());
+        assert!(storage.find(FileId::directory(3)).is_none());
+        assert_eq!("file2", storage.get(FileId::file(2)).name);
+        assert_eq!(5, storage.iter().count());
+    }
+
+    #[test]
+    fn old_tombstones_go_with_the_next_compaction() {
+        let mut storage = test_data();
+        storage.delete(FileId::file(1));
+        storage.seal();
+        storage.delete(FileId::file(2));
+
+        assert_eq!(1, compact_all(&mut storage).files);
+        assert!(storage.find(FileId::file(1)).is_none());
+        assert!(storage.get(FileId::file(2)).data.deleted());
+    }
 }
\ No newline at end of file
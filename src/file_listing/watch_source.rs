@@ -0,0 +1,485 @@
+use dir_walker;
+use dir_walker::ROOT_ID;
+use failure::Error;
+use file_listing::change_source::{
+    ChangeSource,
+    deleted_record,
+    RescanSource,
+    SourceEvent,
+};
+use inotify::{
+    EventMask,
+    Inotify,
+    WatchDescriptor,
+    WatchMask,
+};
+use ntfs::change_journal::{
+    UsnChange,
+    UsnRename,
+};
+use ntfs::file_record::FileRecord;
+use ntfs::volumes::Volume;
+use rusqlite::Connection;
+use slog::Logger;
+use sql;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// errno of inotify_add_watch once fs.inotify.max_user_watches is reached
+const ENOSPC: i32 = 28;
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+type WatchEvent = (WatchDescriptor, EventMask, u32, Option<String>);
+
+/// Follows a walked tree on Linux with an inotify watch on each of its directories.
+pub struct WatchSource {
+    volume: Volume,
+    inotify: Inotify,
+    buffer: Vec<u8>,
+    watches: HashMap<WatchDescriptor, u64>,
+    dir_watches: HashMap<u64, WatchDescriptor>,
+    tree: WatchedTree,
+    conn: Connection,
+}
+
+/// Walks the tree of `volume` into `conn`, watching every directory before it is listed. The changes that
+/// follow are written to `conn` as well. When the system runs out of watches the tree is walked again
+/// every `rescan_interval` instead.
+pub fn open(logger: &Logger, mut conn: Connection, volume: &Volume, rescan_interval: Duration) -> Result<Box<ChangeSource>, Error> {
+    let inotify = Mutex::new(Inotify::init()?);
+    let watches = Mutex::new(Vec::new());
+    let exhausted = AtomicBool::new(false);
+    let files = dir_walker::index(logger, &mut conn, volume, &|dir, id| {
+        if exhausted.load(Ordering::Relaxed) {
+            return;
+        }
+        match inotify.lock().unwrap().add_watch(dir, watch_mask()) {
+            Ok(wd) => watches.lock().unwrap().push((wd, id)),
+            Err(ref e) if e.raw_os_error() == Some(ENOSPC) => exhausted.store(true, Ordering::Relaxed),
+            // gone or unreadable, the walk skips it as well
+            Err(_) => {}
+        }
+    })?;
+    if exhausted.into_inner() {
+        warn!(logger, "out of inotify watches, rescanning periodically"; "interval(s)" => rescan_interval.as_secs());
+        return Ok(Box::new(RescanSource::new(rescan_interval)));
+    }
+    let mut source = WatchSource {
+        volume: volume.clone(),
+        inotify: inotify.into_inner().unwrap(),
+        buffer: vec![0; EVENT_BUFFER_SIZE],
+        watches: HashMap::new(),
+        dir_watches: HashMap::new(),
+        tree: WatchedTree::new(PathBuf::from(&volume.name), &files),
+        conn,
+    };
+    for (wd, id) in watches.into_inner().unwrap() {
+        source.watch(wd, id);
+    }
+    Ok(Box::new(source))
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO | WatchMask::MODIFY | WatchMask::ATTRIB
+        | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF | WatchMask::ONLYDIR | WatchMask::DONT_FOLLOW | WatchMask::EXCL_UNLINK
+}
+
+impl ChangeSource for WatchSource {
+    fn next_event(&mut self) -> Result<SourceEvent, Error> {
+        loop {
+            let events = self.inotify.read_events_blocking(&mut self.buffer)?
+                .map(|e| (e.wd, e.mask, e.cookie, e.name.map(|n| n.to_string_lossy().into_owned())))
+                .collect::<Vec<WatchEvent>>();
+            match self.changes(events) {
+                Some(ref changes) if changes.is_empty() => continue,
+                Some(changes) => {
+                    sql::apply_changes(&mut self.conn, &self.volume, &changes, None);
+                    return Ok(SourceEvent::Changes(changes));
+                }
+                None => return Ok(SourceEvent::Rescan),
+            }
+        }
+    }
+}
+
+impl WatchSource {
+    fn watch(&mut self, wd: WatchDescriptor, id: u64) {
+        self.dir_watches.insert(id, wd.clone());
+        self.watches.insert(wd, id);
+    }
+
+    fn unwatch(&mut self, id: u64) {
+        if let Some(wd) = self.dir_watches.remove(&id) {
+            self.watches.remove(&wd);
+            let _ = self.inotify.rm_watch(wd);
+        }
+    }
+
+    /// None when the events can't be followed and the tree has to be walked again.
+    fn changes(&mut self, events: Vec<WatchEvent>) -> Option<Vec<UsnChange>> {
+        let mut changes = Vec::new();
+        // a move within the tree shows up as MOVED_FROM and MOVED_TO with the same cookie
+        let mut moved_from: HashMap<u32, (u64, String)> = HashMap::new();
+        // writes come in bursts, a file is read again once per batch
+        let mut updated = HashSet::new();
+        for (wd, mask, cookie, name) in events {
+            if mask.contains(EventMask::Q_OVERFLOW) {
+                return None;
+            }
+            let dir = match self.watches.get(&wd) {
+                Some(&dir) => dir,
+                None => continue,
+            };
+            if mask.contains(EventMask::IGNORED) {
+                self.watches.remove(&wd);
+                self.dir_watches.remove(&dir);
+                continue;
+            }
+            if mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF) {
+                if dir == ROOT_ID {
+                    return None;
+                }
+                continue;
+            }
+            let name = match name {
+                Some(name) => name,
+                None => continue,
+            };
+            if mask.contains(EventMask::MOVED_FROM) {
+                moved_from.insert(cookie, (dir, name));
+            } else if mask.contains(EventMask::MOVED_TO) {
+                match moved_from.remove(&cookie) {
+                    Some((old_dir, old_name)) => {
+                        if let Some(id) = self.tree.id(old_dir, &old_name) {
+                            updated.remove(&id);
+                        }
+                        let (moved, removed) = self.tree.moved(old_dir, &old_name, dir, &name);
+                        changes.extend(moved);
+                        self.unwatch_all(removed);
+                    }
+                    None => self.created(dir, &name, &mut changes)?,
+                }
+            } else if mask.contains(EventMask::CREATE) {
+                self.created(dir, &name, &mut changes)?;
+            } else if mask.contains(EventMask::DELETE) {
+                self.deleted(dir, &name, &mut changes);
+            } else if mask.intersects(EventMask::MODIFY | EventMask::ATTRIB) {
+                match self.tree.id(dir, &name) {
+                    Some(id) if !updated.insert(id) => {}
+                    _ => changes.extend(self.tree.updated(dir, &name)),
+                }
+            }
+        }
+        // moved out of the tree
+        for (_, (dir, name)) in moved_from {
+            self.deleted(dir, &name, &mut changes);
+        }
+        Some(changes)
+    }
+
+    /// Anything created in a new directory before its watch was added sends no event, so it is listed once watched.
+    fn created(&mut self, dir: u64, name: &str, changes: &mut Vec<UsnChange>) -> Option<()> {
+        let change = match self.tree.created(dir, name) {
+            Some(change) => change,
+            None => return Some(()),
+        };
+        let new_dir = match change {
+            UsnChange::NEW(ref file) if file.is_directory() => Some(file.header.fr_number),
+            _ => None,
+        };
+        changes.push(change);
+        if let Some(id) = new_dir {
+            let path = self.tree.path(id);
+            match self.inotify.add_watch(&path, watch_mask()) {
+                Ok(wd) => self.watch(wd, id),
+                Err(ref e) if e.raw_os_error() == Some(ENOSPC) => return None,
+                Err(_) => return Some(()),
+            }
+            let names = fs::read_dir(&path).into_iter()
+                .flat_map(|entries| entries)
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<String>>();
+            for name in names {
+                self.created(id, &name, changes)?;
+            }
+        }
+        Some(())
+    }
+
+    fn deleted(&mut self, dir: u64, name: &str, changes: &mut Vec<UsnChange>) {
+        let (deleted, removed) = self.tree.deleted(dir, name);
+        changes.extend(deleted);
+        self.unwatch_all(removed);
+    }
+
+    fn unwatch_all(&mut self, dirs: Vec<u64>) {
+        for id in dirs {
+            self.unwatch(id);
+        }
+    }
+}
+
+struct Entry {
+    parent: u64,
+    name: String,
+    is_dir: bool,
+}
+
+/// The numbers given by the walk to the files of the tree, kept up to date with its changes.
+struct WatchedTree {
+    root: PathBuf,
+    next_id: u64,
+    entries: HashMap<u64, Entry>,
+    children: HashMap<u64, HashMap<String, u64>>,
+}
+
+impl WatchedTree {
+    fn new(root: PathBuf, files: &[FileRecord]) -> Self {
+        let mut tree = WatchedTree {
+            root,
+            next_id: ROOT_ID + 1,
+            entries: HashMap::new(),
+            children: HashMap::new(),
+        };
+        for file in files.iter().filter(|f| f.header.fr_number != ROOT_ID) {
+            let name = &file.name_attrs[0];
+            tree.insert(file.header.fr_number, name.parent_id as u64, &name.name, file.is_directory());
+            tree.next_id = tree.next_id.max(file.header.fr_number + 1);
+        }
+        tree
+    }
+
+    fn insert(&mut self, id: u64, parent: u64, name: &str, is_dir: bool) {
+        self.entries.insert(id, Entry { parent, name: name.to_string(), is_dir });
+        self.children.entry(parent).or_insert_with(HashMap::new).insert(name.to_string(), id);
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Entry> {
+        let entry = self.entries.remove(&id)?;
+        if let Some(siblings) = self.children.get_mut(&entry.parent) {
+            siblings.remove(&entry.name);
+        }
+        Some(entry)
+    }
+
+    fn id(&self, parent: u64, name: &str) -> Option<u64> {
+        self.children.get(&parent).and_then(|c| c.get(name)).cloned()
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        let mut names = Vec::new();
+        let mut current = id;
+        while let Some(entry) = self.entries.get(&current) {
+            names.push(entry.name.as_str());
+            current = entry.parent;
+        }
+        names.into_iter().rev().fold(self.root.clone(), |path, name| path.join(name))
+    }
+
+    fn read(&self, id: u64, parent: u64, name: &str) -> Option<FileRecord> {
+        dir_walker::read_file(&self.path(parent), id, parent, name)
+    }
+
+    /// A file that is known already is only read again.
+    fn created(&mut self, parent: u64, name: &str) -> Option<UsnChange> {
+        if self.id(parent, name).is_some() {
+            return self.updated(parent, name);
+        }
+        let file = self.read(self.next_id, parent, name)?;
+        self.insert(self.next_id, parent, name, file.is_directory());
+        self.next_id += 1;
+        Some(UsnChange::NEW(file))
+    }
+
+    fn updated(&self, parent: u64, name: &str) -> Option<UsnChange> {
+        let id = self.id(parent, name)?;
+        self.read(id, parent, name).map(UsnChange::UPDATE)
+    }
+
+    /// Deletes the file and everything below it, deepest first. Returns the deleted directories too.
+    fn deleted(&mut self, parent: u64, name: &str) -> (Vec<UsnChange>, Vec<u64>) {
+        let mut changes = Vec::new();
+        let mut dirs = Vec::new();
+        if let Some(id) = self.id(parent, name) {
+            self.delete_tree(id, &mut changes, &mut dirs);
+        }
+        (changes, dirs)
+    }
+
+    fn delete_tree(&mut self, id: u64, changes: &mut Vec<UsnChange>, dirs: &mut Vec<u64>) {
+        let children = self.children.remove(&id).map(|c| c.values().cloned().collect()).unwrap_or_else(Vec::new);
+        for child in children {
+            self.delete_tree(child, changes, dirs);
+        }
+        if let Some(entry) = self.remove(id) {
+            if entry.is_dir {
+                dirs.push(id);
+            }
+            changes.push(UsnChange::DELETE(deleted_record(id, entry.parent as i64, entry.is_dir, &entry.name)));
+        }
+    }
+
+    /// A file replaced by the move is deleted first, a file that was not known yet is created.
+    fn moved(&mut self, old_parent: u64, old_name: &str, new_parent: u64, new_name: &str) -> (Vec<UsnChange>, Vec<u64>) {
+        let id = match self.id(old_parent, old_name) {
+            Some(id) => id,
+            None => return (self.created(new_parent, new_name).into_iter().collect(), Vec::new()),
+        };
+        let (mut changes, removed) = self.deleted(new_parent, new_name);
+        let is_dir = self.remove(id).map_or(false, |e| e.is_dir);
+        self.insert(id, new_parent, new_name, is_dir);
+        match self.read(id, new_parent, new_name) {
+            Some(file) => {
+                let rename = UsnRename {
+                    old_parent: old_parent as i64,
+                    old_name: old_name.to_string(),
+                    new_parent: new_parent as i64,
+                    new_name: new_name.to_string(),
+                    file,
+                };
+                changes.push(if old_parent == new_parent { UsnChange::RENAME(rename) } else { UsnChange::MOVE(rename) });
+            }
+            // moved again already, its link is dropped under the old name and comes back with the next event
+            None => {
+                self.remove(id);
+                changes.push(UsnChange::DELETE(deleted_record(id, old_parent as i64, is_dir, old_name)));
+            }
+        }
+        (changes, removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_volume(name: &str) -> Volume {
+        let root = env::temp_dir().join(format!("cloppy-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs").join("report.txt"), b"report").unwrap();
+        Volume { index: 1, name: root.to_string_lossy().into_owned(), kind: ::ntfs::volumes::VolumeKind::DIRECTORY }
+    }
+
+    fn logger() -> Logger {
+        Logger::root(::slog::Discard, o!())
+    }
+
+    fn test_db() -> Connection {
+        sql::setup(Connection::open_in_memory().unwrap())
+    }
+
+    fn next_changes(source: &mut Box<ChangeSource>) -> Vec<UsnChange> {
+        match source.next_event().unwrap() {
+            SourceEvent::Changes(changes) => changes,
+            _ => panic!("expected changes"),
+        }
+    }
+
+    #[test]
+    fn created_and_deleted_files_are_reported() {
+        let volume = temp_volume("watch-create");
+        let root = PathBuf::from(&volume.name);
+        let mut source = open(&logger(), test_db(), &volume, Duration::from_secs(60)).unwrap();
+
+        fs::write(root.join("docs").join("new.txt"), b"1234").unwrap();
+        match next_changes(&mut source)[0] {
+            UsnChange::NEW(ref file) => {
+                assert_eq!("new.txt", file.name_attrs[0].name);
+                assert_eq!(4, file.data_attr.size);
+            }
+            _ => panic!("expected a new file"),
+        }
+        fs::remove_file(root.join("docs").join("report.txt")).unwrap();
+        match next_changes(&mut source)[0] {
+            UsnChange::DELETE(ref record) => assert_eq!("report.txt", record.name),
+            _ => panic!("expected a delete"),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn moves_within_the_tree_keep_the_file() {
+        let volume = temp_volume("watch-move");
+        let root = PathBuf::from(&volume.name);
+        let mut source = open(&logger(), test_db(), &volume, Duration::from_secs(60)).unwrap();
+
+        fs::rename(root.join("docs").join("report.txt"), root.join("report.txt")).unwrap();
+        match next_changes(&mut source)[0] {
+            UsnChange::MOVE(ref rename) => {
+                assert_eq!("report.txt", rename.new_name);
+                assert_eq!(ROOT_ID as i64, rename.new_parent);
+                assert_eq!(rename.new_parent, rename.file.name_attrs[0].parent_id);
+            }
+            _ => panic!("expected a move"),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn new_directories_are_listed_and_watched() {
+        let volume = temp_volume("watch-new-dir");
+        let root = PathBuf::from(&volume.name);
+        let mut source = open(&logger(), test_db(), &volume, Duration::from_secs(60)).unwrap();
+
+        fs::create_dir_all(root.join("music").join("live")).unwrap();
+        let changes = next_changes(&mut source);
+        let names = changes.iter()
+            .filter_map(|c| match *c {
+                UsnChange::NEW(ref file) => Some(file.name_attrs[0].name.clone()),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        assert!(names.contains(&"music".to_string()));
+        assert!(names.contains(&"live".to_string()));
+
+        fs::write(root.join("music").join("live").join("song.mp3"), b"").unwrap();
+        match next_changes(&mut source).last() {
+            Some(&UsnChange::NEW(ref file)) => assert_eq!("song.mp3", file.name_attrs[0].name),
+            _ => panic!("expected a new file"),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn deleting_a_directory_deletes_its_content() {
+        let mut tree = WatchedTree::new(PathBuf::from("/nowhere"), &[]);
+        tree.insert(6, ROOT_ID, "docs", true);
+        tree.insert(7, 6, "old", true);
+        tree.insert(8, 7, "draft.txt", false);
+
+        let (changes, dirs) = tree.deleted(ROOT_ID, "docs");
+        let deleted = changes.iter()
+            .map(|c| match *c {
+                UsnChange::DELETE(ref record) => record.mft_id,
+                _ => panic!("expected deletes only"),
+            })
+            .collect::<Vec<u64>>();
+        assert_eq!(vec![8, 7, 6], deleted);
+        assert_eq!(vec![7, 6], dirs);
+        assert!(tree.id(ROOT_ID, "docs").is_none());
+        assert!(tree.entries.is_empty());
+    }
+
+    #[test]
+    fn path_follows_renamed_parents() {
+        let mut tree = WatchedTree::new(PathBuf::from("/data"), &[]);
+        tree.insert(6, ROOT_ID, "docs", true);
+        tree.insert(7, 6, "draft.txt", false);
+        tree.remove(6);
+        tree.insert(6, ROOT_ID, "papers", true);
+        assert_eq!(PathBuf::from("/data/papers/draft.txt"), tree.path(7));
+    }
+}
@@ -15,6 +15,8 @@ extern crate enum_primitive;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[cfg(target_os = "linux")]
+extern crate inotify;
 extern crate ini;
 #[macro_use]
 extern crate lazy_static;
@@ -45,10 +47,20 @@ use failure::Error;
 use failure::ResultExt;
 use gui::GuiCreateParams;
 use gui::Wnd;
+use ntfs::file_record::FixupPolicy;
+use ntfs::file_record::FixupPolicy::{
+    Reject,
+    Warn,
+};
+use ntfs::volume_source::parse_image;
+use ntfs::volumes::parse_volumes;
 use plugin::Plugin;
 use plugin::State;
 use plugin_handler::PluginHandler;
+use settings::Setting;
+use settings::setting_to_bool;
 use settings::UserSettings;
+use std::env;
 use std::sync::Arc;
 use std::thread;
 
@@ -63,13 +75,13 @@ mod errors;
 mod gui;
 mod resources;
 mod dispatcher;
+mod dir_walker;
 pub mod file_listing;
 mod plugin_handler;
 
 fn main() {
     let logger = logger::setup();
-    let result = ntfs::parse_operation::run(logger.clone())
-        .and_then(|_| try_main(logger.clone()))
+    let result = try_main(logger.clone())
         .map_err(failure_to_string);
     match result {
         Ok(code) => ::std::process::exit(code),
@@ -78,10 +90,20 @@ fn main() {
 }
 
 fn try_main(logger: slog::Logger) -> Result<i32, Error> {
+    if let Some((image, policy)) = image_arg() {
+        return check_image(logger, &image, policy);
+    }
     let settings = UserSettings::load(logger.clone()).context(UserSettingsError)?;
+    let volumes = parse_volumes(
+        settings.get(Setting::Volumes).unwrap_or(Setting::Volumes.default_value()),
+        settings.get(Setting::Directories).unwrap_or(Setting::Directories.default_value()));
+    let fixup_policy = if setting_to_bool(Setting::RejectTornRecords, &settings.get_settings()) { Reject } else { Warn };
+    ntfs::parse_operation::run(logger.clone(), &volumes, fixup_policy)?;
     let (req_snd, req_rcv) = channel::unbounded();
-    let arena = sql::load_all_arena().unwrap();
-    let files = Arc::new(file_listing::FileListing::create(arena, req_snd.clone(), &logger));
+    let show_streams = setting_to_bool(Setting::ShowStreams, &settings.get_settings());
+    let sources = volumes.iter().map(|volume| file_listing::change_source(&logger, volume, fixup_policy)).collect::<Result<Vec<_>, Error>>()?;
+    let arena = sql::load_all_arena(&volumes, show_streams).unwrap();
+    let files = Arc::new(file_listing::FileListing::create(arena, volumes, sources, fixup_policy, req_snd.clone(), &logger));
     let state = State::new("", 0, files.default_plugin_state());
 
     let logger_ui = logger.new(o!("thread" => "ui"));
@@ -101,6 +123,27 @@ fn try_main(logger: slog::Logger) -> Result<i32, Error> {
     Ok(0)
 }
 
+/// `--parse-image <path> [--reject-torn]` parses the MFT of a raw NTFS image and exits, without touching the
+/// database or the UI. Torn records are kept and counted unless `--reject-torn` is given.
+fn image_arg() -> Option<(String, FixupPolicy)> {
+    let mut args = env::args().skip(1);
+    match args.next() {
+        Some(ref flag) if flag == "--parse-image" => {
+            let image = args.next()?;
+            let policy = if args.next().map_or(false, |a| a == "--reject-torn") { Reject } else { Warn };
+            Some((image, policy))
+        }
+        _ => None,
+    }
+}
+
+fn check_image(logger: slog::Logger, image: &str, policy: FixupPolicy) -> Result<i32, Error> {
+    let (files, report) = parse_image(logger.clone(), image, policy)?;
+    info!(logger, "image parsed"; "image" => image, "files" => files.len(), "errors" => report.errors.len(),
+        "torn records" => report.torn_records.len());
+    Ok(if report.errors.is_empty() { 0 } else { 1 })
+}
+
 fn wait_for_wnd(receiver: channel::Receiver<UiAsyncMessage>) -> Option<Wnd> {
     loop {
         let msg = match receiver.recv() {
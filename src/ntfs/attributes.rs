@@ -3,20 +3,43 @@ use byteorder::{
     LittleEndian,
     ReadBytesExt,
 };
+use ntfs::parse_error::{
+    ParseError,
+    read_i64,
+    read_u16,
+    read_u32,
+    read_u64,
+    read_u8,
+    slice,
+};
+use ntfs::parse_error::ParseErrorKind::InvalidLength;
 use ntfs::windows_api::windows_string;
 use std::io::Cursor;
 
 const DATARUN_END: u8 = 0x00;
 const END1: u32 = 0xFFFFFFFF;
-const STANDARD: u32 = 0x10;
+const MIN_ATTRIBUTE_LENGTH: usize = 0x18;
+pub const STANDARD: u32 = 0x10;
+pub const ATTRIBUTE_LIST: u32 = 0x20;
 pub const FILENAME: u32 = 0x30;
 pub const DATA: u32 = 0x80;
+pub const REPARSE_POINT: u32 = 0xC0;
+
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA0000003;
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000000C;
+const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xA000001D;
+const SYMLINK_FLAG_RELATIVE: u32 = 0x01;
+const STANDARD_V3_LENGTH: usize = 0x48;
+const NT_PATH_PREFIX: &str = "\\??\\";
 
 #[derive(Debug, PartialEq)]
 pub enum AttributeType {
     Standard(StandardAttr),
+    AttributeList(AttributeListAttr),
     Filename(FilenameAttr),
     Data(DataAttr),
+    Stream(StreamAttr),
+    ReparsePoint(ReparseAttr),
 }
 
 #[derive(Debug, PartialEq)]
@@ -28,13 +51,63 @@ pub struct Attribute {
 #[derive(Debug, PartialEq, Default)]
 pub struct DataAttr {
     pub size: i64,
+    pub starting_vcn: u64,
     pub datarun: Vec<Datarun>,
 }
 
+/// A named $DATA attribute, e.g. `Zone.Identifier`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct StreamAttr {
+    pub name: String,
+    pub size: i64,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct AttributeListAttr {
+    pub entries: Vec<AttributeListEntry>,
+    pub size: u64,
+    pub datarun: Vec<Datarun>,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct AttributeListEntry {
+    pub attr_type: u32,
+    pub starting_vcn: u64,
+    pub base_reference: u64,
+    pub attr_id: u16,
+    pub name: String,
+}
+
 #[derive(Debug, PartialEq, Default)]
 pub struct StandardAttr {
     pub modified: i64,
     pub created: i64,
+    pub accessed: i64,
+    pub mft_changed: i64,
+    pub dos_attributes: DosAttributes,
+    pub owner_id: Option<u32>,
+    pub security_id: Option<u32>,
+    pub quota_charged: Option<u64>,
+    pub usn: Option<i64>,
+}
+
+bitflags! {
+    #[derive(Default)]
+    pub struct DosAttributes: u32 {
+        const READONLY = 0x0001;
+        const HIDDEN = 0x0002;
+        const SYSTEM = 0x0004;
+        const ARCHIVE = 0x0020;
+        const DEVICE = 0x0040;
+        const NORMAL = 0x0080;
+        const TEMPORARY = 0x0100;
+        const SPARSE = 0x0200;
+        const REPARSE_POINT = 0x0400;
+        const COMPRESSED = 0x0800;
+        const OFFLINE = 0x1000;
+        const NOT_CONTENT_INDEXED = 0x2000;
+        const ENCRYPTED = 0x4000;
+    }
 }
 
 #[derive(Debug, PartialEq, Default)]
@@ -45,12 +118,68 @@ pub struct FilenameAttr {
     pub name: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReparseAttr {
+    Symlink { target: String, relative: bool },
+    MountPoint { target: String },
+    LxSymlink { target: String },
+    Other(u32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Datarun {
     pub length_lcn: u64,
     pub offset_lcn: i64,
 }
 
+impl DataAttr {
+    pub fn is_empty(&self) -> bool {
+        self.size == 0 && self.datarun.is_empty()
+    }
+
+    /// Joins two extents of the same non-resident attribute, found in different records.
+    pub fn merge(&mut self, other: DataAttr) {
+        if other.starting_vcn == 0 {
+            self.size = other.size;
+        }
+        let mut extents = vec![
+            (self.starting_vcn, absolute_lcns(&self.datarun)),
+            (other.starting_vcn, absolute_lcns(&other.datarun))];
+        extents.sort_by_key(|&(vcn, _)| vcn);
+        self.starting_vcn = extents[0].0;
+        let mut prev_lcn = 0;
+        self.datarun = extents.into_iter()
+            .flat_map(|(_, runs)| runs)
+            .map(|(length_lcn, lcn)| {
+                let offset_lcn = lcn - prev_lcn;
+                prev_lcn = lcn;
+                Datarun { length_lcn, offset_lcn }
+            })
+            .collect();
+    }
+}
+
+impl ReparseAttr {
+    pub fn target(&self) -> Option<&str> {
+        match *self {
+            ReparseAttr::Symlink { ref target, .. } => Some(target),
+            ReparseAttr::MountPoint { ref target } => Some(target),
+            ReparseAttr::LxSymlink { ref target } => Some(target),
+            ReparseAttr::Other(_) => None,
+        }
+    }
+}
+
+fn absolute_lcns(dataruns: &[Datarun]) -> Vec<(u64, i64)> {
+    let mut lcn = 0;
+    dataruns.iter()
+        .map(|run| {
+            lcn += run.offset_lcn;
+            (run.length_lcn, lcn)
+        })
+        .collect()
+}
+
 const SEC_TO_UNIX_EPOCH: i64 = 11644473600;
 const WINDOWS_TICK: i64 = 10000000;
 
@@ -76,14 +205,14 @@ fn offset_in_lcn(input: &[u8]) -> i64 {
     }
 }
 
-fn data_attr(input: &[u8]) -> Vec<Datarun> {
+fn data_attr(input: &[u8]) -> Result<Vec<Datarun>, ParseError> {
     let mut offset = 0;
     let mut dataruns = vec![];
     loop {
-        if input[offset] == DATARUN_END {
+        let header = read_u8(input, offset)?;
+        if header == DATARUN_END {
             break;
         }
-        let header = input[offset];
         offset += 1;
         let offset_size = (header >> 4) as usize;
         if offset_size == 0 {
@@ -92,87 +221,219 @@ fn data_attr(input: &[u8]) -> Vec<Datarun> {
             break;
         }
         let length_size = (header & 0x0F) as usize;
-        let length_lcn = length_in_lcn(&input[offset..offset + length_size]);
+        let length_lcn = length_in_lcn(slice(input, offset, length_size)?);
         offset += length_size;
-        let offset_lcn = offset_in_lcn(&input[offset..offset + offset_size]);
+        let offset_lcn = offset_in_lcn(slice(input, offset, offset_size)?);
         dataruns.push(Datarun { length_lcn, offset_lcn });
         offset += offset_size;
     }
-    dataruns
+    Ok(dataruns)
 }
 
-fn filename_attr(input: &[u8]) -> FilenameAttr {
-    let parent_id = LittleEndian::read_i64(input);
-    let flags = LittleEndian::read_u32(&input[0x38..]);
-    let name_length = (input[0x40] as u16 * 2) as usize;
-    let namespace = input[0x41];
-    let name = &input[0x42..0x42 + name_length];
-    FilenameAttr {
+fn filename_attr(input: &[u8]) -> Result<FilenameAttr, ParseError> {
+    let parent_id = read_i64(input, 0)?;
+    let flags = read_u32(input, 0x38)?;
+    let name_length = read_u8(input, 0x40)? as usize * 2;
+    let namespace = read_u8(input, 0x41)?;
+    let name = slice(input, 0x42, name_length)?;
+    Ok(FilenameAttr {
         parent_id,
         namespace,
         dos_flags: flags,
         name: windows_string(name),
+    })
+}
+
+pub fn attribute_list_entries(input: &[u8]) -> Result<Vec<AttributeListEntry>, ParseError> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    while offset < input.len() {
+        let attr_type = read_u32(input, offset)?;
+        if attr_type == END1 {
+            break;
+        }
+        let entry_length = read_u16(input, offset + 0x04)? as usize;
+        if entry_length < 0x1A {
+            return Err(ParseError::new(offset + 0x04, InvalidLength(entry_length)));
+        }
+        let name_length = read_u8(input, offset + 0x06)? as usize * 2;
+        let name_offset = read_u8(input, offset + 0x07)? as usize;
+        entries.push(AttributeListEntry {
+            attr_type,
+            starting_vcn: read_u64(input, offset + 0x08)?,
+            base_reference: read_u64(input, offset + 0x10)?,
+            attr_id: read_u16(input, offset + 0x18)?,
+            name: windows_string(slice(input, offset + name_offset, name_length)?),
+        });
+        offset += entry_length;
+    }
+    Ok(entries)
+}
+
+fn standard_attr(input: &[u8]) -> Result<StandardAttr, ParseError> {
+    let created = win_to_unix_time(read_i64(input, 0)?);
+    let modified = win_to_unix_time(read_i64(input, 0x08)?);
+    let mft_changed = win_to_unix_time(read_i64(input, 0x10)?);
+    let accessed = win_to_unix_time(read_i64(input, 0x18)?);
+    let dos_attributes = DosAttributes::from_bits_truncate(read_u32(input, 0x20)?);
+    let mut standard = StandardAttr { modified, created, accessed, mft_changed, dos_attributes, ..Default::default() };
+    if input.len() >= STANDARD_V3_LENGTH {
+        standard.owner_id = Some(read_u32(input, 0x30)?);
+        standard.security_id = Some(read_u32(input, 0x34)?);
+        standard.quota_charged = Some(read_u64(input, 0x38)?);
+        standard.usn = Some(read_i64(input, 0x40)?);
     }
+    Ok(standard)
 }
 
-fn standard_attr(input: &[u8]) -> StandardAttr {
-    let created = win_to_unix_time(LittleEndian::read_i64(input));
-    let modified = win_to_unix_time(LittleEndian::read_i64(&input[0x08..]));
-    StandardAttr { modified, created }
+fn reparse_attr(input: &[u8]) -> Result<ReparseAttr, ParseError> {
+    let tag = read_u32(input, 0)?;
+    let data_length = read_u16(input, 0x04)? as usize;
+    let data = slice(input, 0x08, data_length)?;
+    reparse_data(tag, data).map_err(|e| e.offset_by(0x08))
 }
 
-pub fn parse_attributes(input: &[u8], last_attr: u32) -> Vec<Attribute> {
+fn reparse_data(tag: u32, data: &[u8]) -> Result<ReparseAttr, ParseError> {
+    let reparse = match tag {
+        IO_REPARSE_TAG_SYMLINK => {
+            let flags = read_u32(data, 0x08)?;
+            let target = reparse_target(data, 0x0C)?;
+            ReparseAttr::Symlink { target, relative: flags & SYMLINK_FLAG_RELATIVE != 0 }
+        }
+        IO_REPARSE_TAG_MOUNT_POINT => ReparseAttr::MountPoint { target: reparse_target(data, 0x08)? },
+        IO_REPARSE_TAG_LX_SYMLINK => {
+            let target = slice(data, 0x04, data.len().saturating_sub(0x04))?;
+            ReparseAttr::LxSymlink { target: String::from_utf8_lossy(target).into_owned() }
+        }
+        _ => ReparseAttr::Other(tag),
+    };
+    Ok(reparse)
+}
+
+/// Reads the print name of a symlink or mount point, falling back to the substitute name.
+fn reparse_target(data: &[u8], path_offset: usize) -> Result<String, ParseError> {
+    let substitute_offset = read_u16(data, 0x00)? as usize;
+    let substitute_length = read_u16(data, 0x02)? as usize;
+    let print_offset = read_u16(data, 0x04)? as usize;
+    let print_length = read_u16(data, 0x06)? as usize;
+    if print_length > 0 {
+        return Ok(windows_string(slice(data, path_offset + print_offset, print_length)?));
+    }
+    let target = windows_string(slice(data, path_offset + substitute_offset, substitute_length)?);
+    Ok(target.trim_left_matches(NT_PATH_PREFIX).to_string())
+}
+
+fn resident_content(input: &[u8], offset: usize) -> Result<(&[u8], usize), ParseError> {
+    let content_length = read_u32(input, offset + 0x10)? as usize;
+    let content_offset = offset + read_u16(input, offset + 0x14)? as usize;
+    let content = slice(input, content_offset, content_length)?;
+    Ok((content, content_offset))
+}
+
+fn nonresident_runs(input: &[u8], offset: usize, attr_length: usize) -> Result<Vec<Datarun>, ParseError> {
+    let runs_offset = read_u16(input, offset + 0x20)? as usize;
+    if runs_offset > attr_length {
+        return Err(ParseError::new(offset + 0x20, InvalidLength(runs_offset)));
+    }
+    let runs = slice(input, offset + runs_offset, attr_length - runs_offset)?;
+    data_attr(runs).map_err(|e| e.offset_by(offset + runs_offset))
+}
+
+pub fn parse_attributes(input: &[u8], last_attr: u32) -> Result<Vec<Attribute>, ParseError> {
     let mut parsed_attributes: Vec<Attribute> = Vec::with_capacity(2);
     let mut offset = 0;
     loop {
-        let attr_type = LittleEndian::read_u32(&input[offset..]);
+        let attr_type = read_u32(input, offset)?;
         if attr_type == END1 || attr_type > last_attr {
             break;
         }
-        let attr_length = LittleEndian::read_u32(&input[offset + 0x04..]) as usize;
-        let non_resident = input[offset + 0x08] == 1;
-        let unnamed = input[offset + 0x09] == 0;
-        let attr_flags = LittleEndian::read_u16(&input[offset + 0x0C..]);
+        let attr_length = read_u32(input, offset + 0x04)? as usize;
+        if attr_length < MIN_ATTRIBUTE_LENGTH {
+            return Err(ParseError::new(offset + 0x04, InvalidLength(attr_length)));
+        }
+        slice(input, offset, attr_length)?;
+        let non_resident = read_u8(input, offset + 0x08)? == 1;
+        let name_length = read_u8(input, offset + 0x09)? as usize * 2;
+        let unnamed = name_length == 0;
+        let attr_flags = read_u16(input, offset + 0x0C)?;
 //        println!("{:X} {}", attr_type, attr_flags);
         if attr_type == STANDARD || attr_type == FILENAME {
-            let attr_offset = LittleEndian::read_u16(&input[offset + 0x14..]) as usize;
+            let (content, content_offset) = resident_content(input, offset)?;
             if attr_type == STANDARD {
-                let standard = standard_attr(&input[offset + attr_offset..]);
+                let standard = standard_attr(content).map_err(|e| e.offset_by(content_offset))?;
                 parsed_attributes.push(Attribute {
                     attr_flags,
                     attr_type: AttributeType::Standard(standard),
                 });
             } else {
-                let filename = filename_attr(&input[offset + attr_offset..]);
+                let filename = filename_attr(content).map_err(|e| e.offset_by(content_offset))?;
                 parsed_attributes.push(Attribute {
                     attr_flags,
                     attr_type: AttributeType::Filename(filename),
                 });
             }
+        } else if attr_type == ATTRIBUTE_LIST {
+            let list = if non_resident {
+                let size = read_u64(input, offset + 0x30)?;
+                let datarun = nonresident_runs(input, offset, attr_length)?;
+                AttributeListAttr { entries: Vec::new(), size, datarun }
+            } else {
+                let (content, content_offset) = resident_content(input, offset)?;
+                let entries = attribute_list_entries(content).map_err(|e| e.offset_by(content_offset))?;
+                AttributeListAttr { entries, size: content.len() as u64, datarun: Vec::new() }
+            };
+            parsed_attributes.push(Attribute {
+                attr_flags,
+                attr_type: AttributeType::AttributeList(list),
+            });
         } else if attr_type == DATA && unnamed {
-            let attr_offset = LittleEndian::read_u16(&input[offset + 0x20..]) as usize;
-            let (datarun, size) = if non_resident {
-                let size = LittleEndian::read_u64(&input[offset + 0x30..]);
-                let vec = data_attr(&input[offset + attr_offset..]);
-                (vec, size)
+            let (datarun, size, starting_vcn) = if non_resident {
+                let starting_vcn = read_u64(input, offset + 0x10)?;
+                let size = read_u64(input, offset + 0x30)?;
+                let vec = nonresident_runs(input, offset, attr_length)?;
+                (vec, size, starting_vcn)
             } else {
-                let size = LittleEndian::read_u32(&input[offset + 0x10..]);
-                (Vec::new(), size as u64)
+                let size = read_u32(input, offset + 0x10)?;
+                (Vec::new(), size as u64, 0)
             };
-            let data = DataAttr { datarun, size: size as i64 };
+            let data = DataAttr { datarun, size: size as i64, starting_vcn };
             parsed_attributes.push(Attribute {
                 attr_flags,
                 attr_type: AttributeType::Data(data),
             });
+        } else if attr_type == DATA {
+            let (starting_vcn, size) = if non_resident {
+                (read_u64(input, offset + 0x10)?, read_u64(input, offset + 0x30)? as i64)
+            } else {
+                (0, read_u32(input, offset + 0x10)? as i64)
+            };
+            // only the first extent of a non resident stream knows its size
+            if starting_vcn == 0 {
+                let name_offset = read_u16(input, offset + 0x0A)? as usize;
+                let name = windows_string(slice(input, offset + name_offset, name_length)?);
+                parsed_attributes.push(Attribute {
+                    attr_flags,
+                    attr_type: AttributeType::Stream(StreamAttr { name, size }),
+                });
+            }
+        } else if attr_type == REPARSE_POINT && !non_resident {
+            //TODO non resident reparse data is not read
+            let (content, content_offset) = resident_content(input, offset)?;
+            let reparse = reparse_attr(content).map_err(|e| e.offset_by(content_offset))?;
+            parsed_attributes.push(Attribute {
+                attr_flags,
+                attr_type: AttributeType::ReparsePoint(reparse),
+            });
         }
         offset += attr_length;
     }
-    parsed_attributes
+    Ok(parsed_attributes)
 }
 
 #[cfg(test)]
 mod tests {
     use ntfs::attributes::AttributeType::*;
+    use ntfs::parse_error::ParseErrorKind::OutOfBounds;
     use super::*;
 
 
@@ -180,7 +441,7 @@ mod tests {
     fn run() {
         let input = [16, 0, 0, 0, 96, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 72, 0, 0, 0, 24, 0, 0, 0, 148, 36, 11, 176, 76, 248, 211, 1, 87, 76, 239, 128, 229, 248, 211, 1, 87, 76, 239, 128, 229, 248, 211, 1, 87, 76, 239, 128, 229, 248, 211, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 172, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 176, 114, 180, 163, 0, 0, 0, 0, 32, 0, 0, 0, 16, 1, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 248, 0, 0, 0, 24, 0, 0, 0, 16, 0, 0, 0, 32, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 0, 0, 68, 67, 0, 0, 0, 0, 48, 0, 0, 0, 32, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 3, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 32, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0, 0, 47, 216, 0, 0, 0, 0, 243, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 32, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 8, 0, 0, 0, 0, 0, 0, 0, 144, 0, 0, 0, 40, 0, 4, 26, 0, 0, 0, 0, 0, 0, 0, 0, 47, 216, 0, 0, 0, 0, 243, 0, 1, 0, 36, 0, 73, 0, 51, 0, 48, 0, 0, 0, 0, 0, 0, 0, 160, 0, 0, 0, 40, 0, 4, 26, 0, 0, 0, 0, 0, 0, 0, 0, 47, 216, 0, 0, 0, 0, 243, 0, 2, 0, 36, 0, 73, 0, 51, 0, 48, 0, 0, 0, 0, 0, 0, 0, 176, 0, 0, 0, 40, 0, 4, 26, 0, 0, 0, 0, 0, 0, 0, 0, 47, 216, 0, 0, 0, 0, 243, 0, 3, 0, 36, 0, 73, 0, 51, 0, 48, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 120, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 90, 0, 0, 0, 24, 0, 1, 0, 90, 118, 8, 0, 0, 0, 11, 0, 148, 36, 11, 176, 76, 248, 211, 1, 148, 36, 11, 176, 76, 248, 211, 1, 148, 36, 11, 176, 76, 248, 211, 1, 148, 36, 11, 176, 76, 248, 211, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 12, 2, 49, 0, 55, 0, 72, 0, 83, 0, 71, 0, 69, 0, 126, 0, 49, 0, 46, 0, 68, 0, 69, 0, 70, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 16, 0, 0, 0, 24, 0, 0, 0, 11, 200, 160, 192, 134, 100, 232, 17, 131, 46, 68, 138, 91, 153, 127, 34, 255, 255, 255, 255, 130, 121, 71, 17, 105, 0, 116, 0, 101, 0, 45, 0, 115, 0, 104, 0, 109, 0, 19, 0, 0, 0, 0, 0, 0, 0, 0, 0, 151, 214, 0, 0, 0, 0, 18, 0, 128, 0, 102, 0, 1, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 241, 57, 85, 176, 76, 248, 211, 1, 200, 243, 126, 15, 78, 248, 211, 1, 200, 243, 126, 15, 78, 248, 211, 1, 241, 57, 85, 176, 76, 248, 211, 1, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 18, 1, 102, 0, 111, 0, 114, 0, 109, 0, 104, 0, 105, 0, 115, 0, 116, 0, 111, 0, 114, 0, 121, 0, 46, 0, 115, 0, 113, 0, 108, 0, 105, 0, 116, 0, 101, 0, 105, 1, 3, 0, 0, 0, 0, 0, 0, 0, 244, 11, 0, 0, 0, 0, 210, 3, 120, 0, 90, 0, 1, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 156, 60, 109, 185, 76, 248, 211, 1, 164, 99, 109, 185, 76, 248, 211, 1, 164, 99, 109, 185, 76, 248, 211, 1, 156, 60, 109, 185, 76, 248, 211, 1, 0, 128, 0, 0, 0, 0, 0, 0, 0, 128, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 12, 2, 80, 0, 76, 0, 65, 0, 67, 0, 69, 0, 83, 0, 126, 0, 51, 0, 46, 0, 83, 0, 81, 0, 76, 0, 0, 0, 0, 0, 14, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 24, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 160, 0, 0, 0, 88, 0, 0, 0, 1, 4, 64, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 72, 0, 0, 0, 0, 0, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 36, 0, 73, 0, 51, 0, 48, 0, 49, 1, 175, 245, 12, 33, 1, 33, 60, 33, 2, 104, 62, 0, 0, 0, 176, 0, 0, 0, 40, 0, 0, 0, 0, 4, 24, 0, 0, 0, 5, 0, 8, 0, 0, 0, 32, 0, 0, 0, 36, 0, 73, 0, 51, 0, 48, 0, 31, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 130, 121, 71, 17];
         println!("{}", 3096224744371802u64 as u32);
-        println!("{:?}", parse_attributes(&input, DATA));
+        println!("{:?}", parse_attributes(&input, DATA).unwrap());
     }
 
     #[test]
@@ -213,31 +474,52 @@ mod tests {
             Datarun { length_lcn: 51693, offset_lcn: 15980894 },
             Datarun { length_lcn: 60232, offset_lcn: 7969036 },
             Datarun { length_lcn: 329407, offset_lcn: 14682940 }];
-        assert_eq!(&output, data_attr(&input).as_slice());
+        assert_eq!(&output, data_attr(&input).unwrap().as_slice());
     }
 
     #[test]
     fn test_filename_attr() {
         let input = [5, 0, 0, 0, 0, 0, 5, 0, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 0, 64, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 4, 3, 36, 0, 77, 0, 70, 0, 84, 0];
         let output = FilenameAttr { parent_id: 1407374883553285, dos_flags: 6, namespace: 3, name: "$MFT".to_string() };
-        assert_eq!(output, filename_attr(&input));
+        assert_eq!(output, filename_attr(&input).unwrap());
     }
 
     #[test]
     fn test_standard_attr() {
         let input = [82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 6, 0, 0, 0];
-        let output = StandardAttr { modified: 1445836384, created: 1445836384 };
-        assert_eq!(output, standard_attr(&input));
+        let output = StandardAttr {
+            modified: 1445836384,
+            created: 1445836384,
+            accessed: 1445836384,
+            mft_changed: 1445836384,
+            dos_attributes: DosAttributes::HIDDEN | DosAttributes::SYSTEM,
+            ..Default::default()
+        };
+        assert_eq!(output, standard_attr(&input).unwrap());
     }
 
     #[test]
     fn test_parse_attributes() {
         let output = vec![
-            Attribute { attr_flags: 0, attr_type: Standard(StandardAttr { modified: 1445836384, created: 1445836384 }) },
+            Attribute {
+                attr_flags: 0,
+                attr_type: Standard(StandardAttr {
+                    modified: 1445836384,
+                    created: 1445836384,
+                    accessed: 1445836384,
+                    mft_changed: 1445836384,
+                    dos_attributes: DosAttributes::HIDDEN | DosAttributes::SYSTEM,
+                    owner_id: Some(0),
+                    security_id: Some(256),
+                    quota_charged: Some(0),
+                    usn: Some(0),
+                }),
+            },
             Attribute { attr_flags: 0, attr_type: Filename(FilenameAttr { parent_id: 1407374883553285, dos_flags: 6, namespace: 3, name: "$MFT".to_string() }) },
             Attribute {
                 attr_flags: 0,
                 attr_type: Data(DataAttr {
+                    starting_vcn: 0,
                     datarun: vec![
                         Datarun { length_lcn: 51232, offset_lcn: 786432 },
                         Datarun { length_lcn: 53228, offset_lcn: 10043766 },
@@ -248,7 +530,7 @@ mod tests {
                 }),
             }];
         let input = [16, 0, 0, 0, 96, 0, 0, 0, 0, 0, 24, 0, 0, 0, 0, 0, 72, 0, 0, 0, 24, 0, 0, 0, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 104, 0, 0, 0, 0, 0, 24, 0, 0, 0, 3, 0, 74, 0, 0, 0, 24, 0, 1, 0, 5, 0, 0, 0, 0, 0, 5, 0, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 0, 64, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 4, 3, 36, 0, 77, 0, 70, 0, 84, 0, 0, 0, 0, 0, 0, 0, 128, 0, 0, 0, 104, 0, 0, 0, 1, 0, 64, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 83, 8, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 133, 0, 0, 0, 0, 0, 0, 64, 133, 0, 0, 0, 0, 0, 0, 64, 133, 0, 0, 0, 0, 51, 32, 200, 0, 0, 0, 12, 67, 236, 207, 0, 118, 65, 153, 0, 67, 237, 201, 0, 94, 217, 243, 0, 51, 72, 235, 0, 12, 153, 121, 67, 191, 6, 5, 60, 11, 224, 0, 0, 0, 176, 0, 0, 0];
-        assert_eq!(output, parse_attributes(&input, DATA));
+        assert_eq!(output, parse_attributes(&input, DATA).unwrap());
     }
 
     #[test]
@@ -260,10 +542,12 @@ mod tests {
                 attr_type: Data(
                     DataAttr {
                         size: 131072,
+                        starting_vcn: 0,
                         datarun: vec![Datarun { length_lcn: 32, offset_lcn: 3 }],
                     }),
-            }];
-        assert_eq!(output, parse_attributes(&input, DATA));
+            },
+            Attribute { attr_flags: 0, attr_type: Stream(StreamAttr { name: "$Info".to_string(), size: 32 }) }];
+        assert_eq!(output, parse_attributes(&input, DATA).unwrap());
     }
 
     #[test]
@@ -275,15 +559,16 @@ mod tests {
                 attr_type: Data(
                     DataAttr {
                         size: 186,
+                        starting_vcn: 0,
                         datarun: vec![],
                     }
                 ),
             }];
-        assert_eq!(output, parse_attributes(&input, DATA));
+        assert_eq!(output, parse_attributes(&input, DATA).unwrap());
     }
 
     #[test]
-    fn ignore_data_attr_except_unnamed() {
+    fn named_data_attr_is_a_stream() {
         let input = [16, 0, 0, 0, 96, 0, 0, 0, 0, 0, 24, 0, 0, 0, 0, 0, 72, 0, 0, 0, 24, 0, 0, 0, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 104, 0, 0, 0, 0, 0, 24, 0, 0, 0, 2, 0, 80, 0, 0, 0, 24, 0, 1, 0, 5, 0, 0, 0, 0, 0, 5, 0, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 82, 131, 14, 254, 172, 15, 209, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, 7, 3, 36, 0, 85, 0, 112, 0, 67, 0, 97, 0, 115, 0, 101, 0, 128, 0, 0, 0, 72, 0, 0, 0, 1, 0, 64, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 31, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 17, 32, 3, 0, 0, 0, 0, 0, 128, 0, 0, 0, 72, 0, 0, 0, 0, 5, 24, 0, 0, 0, 4, 0, 32, 0, 0, 0, 40, 0, 0, 0, 36, 0, 73, 0, 110, 0, 102, 0, 111, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 12, 105, 27, 107, 119, 126, 220, 218, 6, 0, 0, 0, 3, 0, 0, 0, 128, 37, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 17, 32, 3, 0, 0, 0, 0, 0, 128, 0, 0, 0, 72, 0, 0, 0, 0, 5, 24, 0, 0, 0, 4, 0, 32, 0, 0, 0, 40, 0, 0, 0, 36, 0, 73, 0, 110, 0, 102, 0, 111, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 12, 105, 27, 107, 119, 126, 220, 218, 6, 0, 0, 0, 3, 0, 0, 0, 128, 37, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
         let output = vec![
             Attribute {
@@ -292,6 +577,13 @@ mod tests {
                     StandardAttr {
                         modified: 1445836384,
                         created: 1445836384,
+                        accessed: 1445836384,
+                        mft_changed: 1445836384,
+                        dos_attributes: DosAttributes::HIDDEN | DosAttributes::SYSTEM,
+                        owner_id: Some(0),
+                        security_id: Some(256),
+                        quota_charged: Some(0),
+                        usn: Some(0),
                     }
                 ),
             },
@@ -311,6 +603,7 @@ mod tests {
                 attr_type: Data(
                     DataAttr {
                         size: 131072,
+                        starting_vcn: 0,
                         datarun: vec![
                             Datarun {
                                 length_lcn: 32,
@@ -319,15 +612,106 @@ mod tests {
                         ],
                     }
                 ),
+            },
+            Attribute {
+                attr_flags: 0,
+                attr_type: Stream(
+                    StreamAttr {
+                        name: "$Info".to_string(),
+                        size: 32,
+                    }
+                ),
             }
         ];
-        assert_eq!(output, parse_attributes(&input, DATA));
+        assert_eq!(output, parse_attributes(&input, DATA).unwrap());
     }
 
     #[test]
     fn handle_sparse_dataruns() {
         let input = [4, 255, 188, 158, 3, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 24, 0, 0, 0, 2, 0, 0, 0, 0, 0, 24, 0, 0, 0, 128, 0, 0, 0, 80, 0, 0, 0, 1, 4, 64, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 254, 9, 163, 3, 0, 0, 0, 0, 72, 0, 0, 0, 0, 0, 0, 0, 0, 240, 159, 48, 58, 0, 0, 0, 0, 240, 159, 48, 58, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 36, 0, 66, 0, 97, 0, 100, 0, 4, 255, 9, 163, 3, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-        assert!(data_attr(&input).is_empty());
+        assert!(data_attr(&input).unwrap().is_empty());
     }
-}
 
+    #[test]
+    fn test_attribute_list_entries() {
+        let input = [16, 0, 0, 0, 32, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0, 0, 125, 72, 0, 0, 0, 0, 105, 1, 0, 0, 0, 0, 0, 0, 0, 0, 128, 0, 0, 0, 40, 0, 4, 26, 0, 0, 0, 0, 0, 0, 0, 0, 47, 216, 0, 0, 0, 0, 243, 0, 1, 0, 36, 0, 73, 0, 51, 0, 48, 0, 0, 0, 0, 0, 0, 0];
+        let output = vec![
+            AttributeListEntry { attr_type: STANDARD, starting_vcn: 0, base_reference: 101612466592565373, attr_id: 0, name: "".to_string() },
+            AttributeListEntry { attr_type: DATA, starting_vcn: 0, base_reference: 68398419340744751, attr_id: 1, name: "$I30".to_string() }];
+        assert_eq!(output, attribute_list_entries(&input).unwrap());
+    }
+
+    #[test]
+    fn merge_data_extents_in_vcn_order() {
+        let mut first = DataAttr {
+            size: 4096 * 30,
+            starting_vcn: 0,
+            datarun: vec![Datarun { length_lcn: 10, offset_lcn: 100 }, Datarun { length_lcn: 10, offset_lcn: -50 }],
+        };
+        let last = DataAttr {
+            size: 0,
+            starting_vcn: 20,
+            datarun: vec![Datarun { length_lcn: 10, offset_lcn: 500 }],
+        };
+        first.merge(last);
+        let expected = DataAttr {
+            size: 4096 * 30,
+            starting_vcn: 0,
+            datarun: vec![
+                Datarun { length_lcn: 10, offset_lcn: 100 },
+                Datarun { length_lcn: 10, offset_lcn: -50 },
+                Datarun { length_lcn: 10, offset_lcn: 450 }],
+        };
+        assert_eq!(expected, first);
+    }
+
+    #[test]
+    fn merge_data_extents_out_of_order() {
+        let mut last = DataAttr {
+            size: 0,
+            starting_vcn: 10,
+            datarun: vec![Datarun { length_lcn: 10, offset_lcn: 500 }],
+        };
+        let first = DataAttr {
+            size: 4096 * 20,
+            starting_vcn: 0,
+            datarun: vec![Datarun { length_lcn: 10, offset_lcn: 100 }],
+        };
+        last.merge(first);
+        let expected = DataAttr {
+            size: 4096 * 20,
+            starting_vcn: 0,
+            datarun: vec![Datarun { length_lcn: 10, offset_lcn: 100 }, Datarun { length_lcn: 10, offset_lcn: 400 }],
+        };
+        assert_eq!(expected, last);
+    }
+
+    #[test]
+    fn reparse_symlink() {
+        let input = [12, 0, 0, 160, 48, 0, 0, 0, 0, 0, 18, 0, 18, 0, 18, 0, 1, 0, 0, 0, 46, 0, 46, 0, 92, 0, 116, 0, 97, 0, 114, 0, 103, 0, 101, 0, 116, 0, 46, 0, 46, 0, 92, 0, 116, 0, 97, 0, 114, 0, 103, 0, 101, 0, 116, 0];
+        let output = ReparseAttr::Symlink { target: "..\\target".to_string(), relative: true };
+        assert_eq!(output, reparse_attr(&input).unwrap());
+    }
+
+    #[test]
+    fn reparse_mount_point_without_print_name() {
+        let input = [3, 0, 0, 160, 32, 0, 0, 0, 0, 0, 24, 0, 24, 0, 0, 0, 92, 0, 63, 0, 63, 0, 92, 0, 67, 0, 58, 0, 92, 0, 85, 0, 115, 0, 101, 0, 114, 0, 115, 0];
+        let output = ReparseAttr::MountPoint { target: "C:\\Users".to_string() };
+        assert_eq!(output, reparse_attr(&input).unwrap());
+    }
+
+    #[test]
+    fn reparse_lx_symlink_and_other_tags() {
+        let input = [29, 0, 0, 160, 10, 0, 0, 0, 2, 0, 0, 0, 47, 109, 110, 116, 47, 99];
+        assert_eq!(ReparseAttr::LxSymlink { target: "/mnt/c".to_string() }, reparse_attr(&input).unwrap());
+
+        let input = [0x17, 0, 0, 0x80, 0, 0, 0, 0];
+        assert_eq!(ReparseAttr::Other(0x80000017), reparse_attr(&input).unwrap());
+    }
+
+    #[test]
+    fn reparse_target_out_of_bounds() {
+        let input = [3, 0, 0, 160, 8, 0, 0, 0, 0, 0, 24, 0, 24, 0, 0, 0];
+        assert_eq!(ParseError::new(0x10, OutOfBounds(24)), reparse_attr(&input).unwrap_err());
+    }
+}
@@ -0,0 +1,89 @@
+use ntfs::windows_api::UsnJournal as WinJournal;
+
+/// Why the journal can't be read from the saved position anymore, the index has to be rebuilt.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Fail)]
+pub enum JournalLoss {
+    #[fail(display = "change journal is disabled")]
+    Disabled,
+    #[fail(display = "change journal was deleted and recreated")]
+    Recreated,
+    #[fail(display = "change journal no longer holds usn {}", _0)]
+    Wrapped(i64),
+}
+
+/// Where the index stands in the change journal of its volume.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct JournalState {
+    pub usn_journal_id: u64,
+    pub next_usn: i64,
+}
+
+impl JournalState {
+    /// The saved position can be read back only from the same journal, and only if the records
+    /// from there on were not purged yet.
+    pub fn can_resume(&self, current: &WinJournal) -> bool {
+        self.loss(Some(current)).is_none()
+    }
+
+    /// `current` is None when the journal could not be queried at all.
+    pub fn loss(&self, current: Option<&WinJournal>) -> Option<JournalLoss> {
+        match current {
+            None => Some(JournalLoss::Disabled),
+            Some(current) if self.usn_journal_id != current.usn_journal_id => Some(JournalLoss::Recreated),
+            Some(current) if self.next_usn > current.next_usn => Some(JournalLoss::Recreated),
+            Some(current) if self.next_usn < current.lowest_valid_usn => Some(JournalLoss::Recreated),
+            Some(current) if self.next_usn < current.first_usn => Some(JournalLoss::Wrapped(self.next_usn)),
+            Some(_) => None,
+        }
+    }
+}
+
+impl From<WinJournal> for JournalState {
+    fn from(journal: WinJournal) -> Self {
+        JournalState { usn_journal_id: journal.usn_journal_id, next_usn: journal.next_usn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal() -> WinJournal {
+        WinJournal { usn_journal_id: 7, first_usn: 1000, lowest_valid_usn: 200, next_usn: 5000 }
+    }
+
+    #[test]
+    fn resumes_same_journal() {
+        assert!(JournalState { usn_journal_id: 7, next_usn: 1000 }.can_resume(&journal()));
+        assert!(JournalState { usn_journal_id: 7, next_usn: 5000 }.can_resume(&journal()));
+    }
+
+    #[test]
+    fn rescans_after_journal_recreated() {
+        assert!(!JournalState { usn_journal_id: 8, next_usn: 2000 }.can_resume(&journal()));
+        assert!(!JournalState { usn_journal_id: 7, next_usn: 6000 }.can_resume(&journal()));
+    }
+
+    #[test]
+    fn rescans_after_records_purged() {
+        assert!(!JournalState { usn_journal_id: 7, next_usn: 999 }.can_resume(&journal()));
+        assert!(!JournalState { usn_journal_id: 7, next_usn: 200 }.can_resume(&journal()));
+    }
+
+    #[test]
+    fn rescans_after_journal_stamped() {
+        let state = JournalState { usn_journal_id: 7, next_usn: 100 };
+        assert_eq!(Some(JournalLoss::Recreated), state.loss(Some(&journal())));
+    }
+
+    #[test]
+    fn loss_tells_why() {
+        let state = JournalState { usn_journal_id: 7, next_usn: 999 };
+        assert_eq!(Some(JournalLoss::Wrapped(999)), state.loss(Some(&journal())));
+        assert_eq!(Some(JournalLoss::Disabled), state.loss(None));
+        let state = JournalState { usn_journal_id: 8, next_usn: 2000 };
+        assert_eq!(Some(JournalLoss::Recreated), state.loss(Some(&journal())));
+        let state = JournalState { usn_journal_id: 7, next_usn: 2000 };
+        assert_eq!(None, state.loss(Some(&journal())));
+    }
+}
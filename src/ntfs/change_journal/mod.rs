@@ -1,6 +1,11 @@
+pub use ntfs::change_journal::journal_state::JournalLoss;
+pub use ntfs::change_journal::journal_state::JournalState;
+pub use ntfs::change_journal::usn_journal::current_journal;
 pub use ntfs::change_journal::usn_journal::UsnJournal;
 pub use self::usn_record::UsnChange;
 pub use self::usn_record::UsnRecord;
+pub use self::usn_record::UsnRename;
 
+mod journal_state;
 mod usn_journal;
 mod usn_record;
@@ -1,18 +1,33 @@
-use byteorder::{ByteOrder, LittleEndian};
 use errors::MyErrorKind::UsnJournalError;
 use failure::{
     Error,
     ResultExt,
 };
+use ntfs::change_journal::journal_state::{
+    JournalLoss,
+    JournalState,
+};
+use ntfs::change_journal::usn_record::into_changes;
+use ntfs::change_journal::usn_record::usn_records;
 use ntfs::change_journal::usn_record::UsnChange;
 use ntfs::change_journal::usn_record::UsnRecord;
-use ntfs::file_record::FileRecord;
+use ntfs::file_record::{
+    FileRecord,
+    FixupPolicy,
+};
+use ntfs::mft_parser::ParseReport;
+use ntfs::parse_error::ParseErrorKind::TornRecord;
 use ntfs::volume_data::VolumeData;
+use ntfs::volume_source::{
+    LiveVolume,
+    load_attribute_list,
+    VolumeSource,
+};
 use ntfs::windows_api::get_file_record;
 use ntfs::windows_api::get_usn_journal;
-use ntfs::windows_api::get_volume_data;
 use ntfs::windows_api::read_usn_journal;
 use ntfs::windows_api::UsnJournal as WinJournal;
+use std::collections::HashMap;
 use std::fs::File;
 use std::mem;
 use std::path::Path;
@@ -20,44 +35,118 @@ use winapi::shared::minwindef::BYTE;
 use winapi::um::winioctl::NTFS_FILE_RECORD_OUTPUT_BUFFER;
 
 pub struct UsnJournal {
-    volume: File,
+    volume: LiveVolume,
     volume_data: VolumeData,
     usn_journal_id: u64,
     next_usn: i64,
+    fixup_policy: FixupPolicy,
+    old_names: HashMap<i64, UsnRecord>,
+}
+
+pub fn current_journal<P: AsRef<Path>>(volume_path: P) -> Result<WinJournal, Error> {
+    let volume = File::open(volume_path).context(UsnJournalError)?;
+    Ok(get_usn_journal(&volume).context(UsnJournalError)?)
 }
 
 impl UsnJournal {
-    pub fn new<P: AsRef<Path>>(volume_path: P) -> Result<Self, Error> {
-        let volume = File::open(volume_path).context(UsnJournalError)?;
-        let volume_data = get_volume_data(&volume).map(VolumeData::new).context(UsnJournalError)?;
-        let WinJournal { usn_journal_id, next_usn } = get_usn_journal(&volume).context(UsnJournalError)?;
+    /// Reads the journal from `state` on, which replays whatever changed since it was saved.
+    pub fn new<P: AsRef<Path>>(volume_path: P, state: JournalState, fixup_policy: FixupPolicy) -> Result<Self, Error> {
+        let volume = LiveVolume::open(volume_path).context(UsnJournalError)?;
+        let volume_data = volume.volume_data();
+        let JournalState { usn_journal_id, next_usn } = state;
         Ok(UsnJournal {
             volume,
             volume_data,
             usn_journal_id,
             next_usn,
+            fixup_policy,
+            old_names: HashMap::new(),
         })
     }
 
-    pub fn get_new_changes(&mut self) -> Result<Vec<UsnChange>, Error> {
+    pub fn state(&self) -> JournalState {
+        JournalState { usn_journal_id: self.usn_journal_id, next_usn: self.next_usn }
+    }
+
+    /// Asks the volume whether the journal we are reading is still there, after a read failed.
+    pub fn loss(&self) -> Option<JournalLoss> {
+        let current = get_usn_journal(self.volume.file()).ok();
+        self.state().loss(current.as_ref())
+    }
+
+    /// Records that can't be parsed or read are left out of the batch and reported, reading them again wouldn't
+    /// help. The batch still moves the journal position past them.
+    pub fn get_new_changes(&mut self) -> Result<(Vec<UsnChange>, ParseReport), Error> {
+        let mut report = ParseReport::default();
         let mut buffer = vec![0u8; self.volume_data.bytes_per_cluster as usize];
         let mut output_buffer = [0u8; mem::size_of::<NTFS_FILE_RECORD_OUTPUT_BUFFER>() + mem::size_of::<BYTE>() * 4096];
-        let buffer = read_usn_journal(&self.volume, self.next_usn, self.usn_journal_id, &mut buffer).context(UsnJournalError)?;
+        // a purged position comes back as a `JournalLoss`, which is why the error isn't wrapped
+        let buffer = read_usn_journal(self.volume.file(), self.next_usn, self.usn_journal_id, &mut buffer)?;
+        let (next_usn, records) = usn_records(buffer, &mut report.errors).context(UsnJournalError)?;
         let mut usn_records = vec![];
-        let next_usn = LittleEndian::read_i64(buffer);
-        let mut offset = 8;
-        loop {
-            if offset == buffer.len() {
-                break;
+        for record in records.into_iter().filter(|r| !r.is_range_tracking()) {
+            let file = match get_file_record(self.volume.file(), record.fr_number, &mut output_buffer) {
+                Ok(fr_buffer) => self.parse_record(fr_buffer, &mut report),
+                Err(_) => {
+                    report.unreadable.push(record.mft_id);
+                    None
+                }
+            };
+            if let Some(mut f) = file {
+                if f.has_attribute_list() {
+                    self.merge_extensions(&mut f, &mut output_buffer, &mut report);
+                }
+                usn_records.push((record, f));
             }
-            let record = UsnRecord::new(&buffer[offset..]).context(UsnJournalError)?;
-            offset += record.length;
-
-            let fr_buffer = get_file_record(&self.volume, record.fr_number, &mut output_buffer).unwrap();
-            let entry = FileRecord::parse_mft_entry(fr_buffer, self.volume_data);
-            entry.map(|f| usn_records.push(record.into_change(f)));
         }
         self.next_usn = next_usn;
-        Ok(usn_records)
+        Ok((into_changes(usn_records, &mut self.old_names), report))
     }
-}
\ No newline at end of file
+
+    /// The volume only hands out the base record, the records its attribute list points to are read one by one.
+    /// The file keeps what its base record holds when they can't be read.
+    fn merge_extensions(&mut self, file: &mut FileRecord, output_buffer: &mut [u8], report: &mut ParseReport) {
+        let record = file.header.fr_number;
+        if let Some(list) = file.attribute_list.as_mut() {
+            if load_attribute_list(&mut self.volume, list).is_err() {
+                report.unreadable.push(record);
+                return;
+            }
+        }
+        for number in file.extension_records() {
+            let fr_buffer = match get_file_record(self.volume.file(), number as i64, output_buffer) {
+                Ok(fr_buffer) => fr_buffer,
+                Err(_) => {
+                    report.unreadable.push(number);
+                    continue;
+                }
+            };
+            match self.parse_record(fr_buffer, report) {
+                // the volume answers with the closest record in use when the one asked for isn't
+                Some(ref extension) if extension.base_record_number() != record => {}
+                Some(extension) => file.merge(extension),
+                None => {}
+            }
+        }
+    }
+
+    fn parse_record(&self, buffer: &mut [u8], report: &mut ParseReport) -> Option<FileRecord> {
+        match FileRecord::parse_mft_entry(buffer, self.volume_data, self.fixup_policy) {
+            Ok(Some(f)) => {
+                if f.header.torn {
+                    report.torn_records.push(f.header.fr_number);
+                }
+                Some(f)
+            }
+            Ok(None) => None,
+            Err(ref e) if e.reason == TornRecord => {
+                report.torn_records.push(e.record);
+                None
+            }
+            Err(e) => {
+                report.errors.push(e);
+                None
+            }
+        }
+    }
+}
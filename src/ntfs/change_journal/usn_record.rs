@@ -1,21 +1,58 @@
-use byteorder::{ByteOrder, LittleEndian};
-use errors::MyErrorKind::UsnRecordVersionUnsupported;
-use failure::Error;
-use ntfs::file_record::FileRecord;
-use ntfs::windows_api::windows_string;
+use ntfs::file_record::{
+    FileRecord,
+    RECORD_NUMBER_MASK,
+};
+use ntfs::parse_error::{
+    ParseError,
+    ParseErrorKind::*,
+    read_i64,
+    read_u128,
+    read_u16,
+    read_u32,
+    read_u64,
+    slice,
+};
+use ntfs::windows_api::{
+    windows_string,
+    WinUsnChanges,
+};
+use std::collections::HashMap;
+
+const USN_RECORD_V2_SIZE: usize = 0x3C;
+const USN_RECORD_V3_SIZE: usize = 0x4C;
+const USN_RECORD_V4_SIZE: usize = 0x50;
+const RANGE_TRACKING_VERSION: u16 = 4;
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
 
 #[derive(Debug, PartialEq)]
 pub enum UsnChange {
     NEW(FileRecord),
     UPDATE(FileRecord),
     DELETE(UsnRecord),
+    /// A link renamed inside the same directory.
+    RENAME(UsnRename),
+    /// A link moved to another directory, possibly renamed on the way.
+    MOVE(UsnRename),
     IGNORE,
 }
 
+/// The link of `file` that was `old_name` under `old_parent` and is now `new_name` under `new_parent`.
+#[derive(Debug, PartialEq)]
+pub struct UsnRename {
+    pub old_parent: i64,
+    pub old_name: String,
+    pub new_parent: i64,
+    pub new_name: String,
+    pub file: FileRecord,
+}
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct UsnRecord {
+    pub version: u16,
+    pub file_id: u128,
+    pub parent_file_id: u128,
     pub fr_number: i64,
-    pub mft_id: u32,
+    pub mft_id: u64,
     pub seq_number: u16,
     pub parent_fr_number: i64,
     pub reason: u32,
@@ -25,91 +62,374 @@ pub struct UsnRecord {
     pub name: String,
 }
 
-bitflags! {
-    struct WinUsnChanges: u32 {
-        const FILE_CREATE= 0x00000100;
-        const FILE_DELETE= 0x00000200;
-        const RENAME_NEW_NAME= 0x00002000;
-        const BASIC_INFO_CHANGE= 0x00008000;
-        const CLOSE= 0x80000000;
+/// What a single reason bit means for the index, from weakest to strongest.
+#[derive(Copy, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum ReasonAction {
+    Ignore,
+    Update,
+    Create,
+    Delete,
+}
+
+const REASON_ACTIONS: &[(WinUsnChanges, ReasonAction)] = &[
+    // overwrites keep the size but move the modified time
+    (WinUsnChanges::DATA_OVERWRITE, ReasonAction::Update),
+    (WinUsnChanges::DATA_EXTEND, ReasonAction::Update),
+    (WinUsnChanges::DATA_TRUNCATION, ReasonAction::Update),
+    (WinUsnChanges::NAMED_DATA_OVERWRITE, ReasonAction::Update),
+    (WinUsnChanges::NAMED_DATA_EXTEND, ReasonAction::Update),
+    (WinUsnChanges::NAMED_DATA_TRUNCATION, ReasonAction::Update),
+    (WinUsnChanges::FILE_CREATE, ReasonAction::Create),
+    (WinUsnChanges::FILE_DELETE, ReasonAction::Delete),
+    (WinUsnChanges::EA_CHANGE, ReasonAction::Ignore),
+    (WinUsnChanges::SECURITY_CHANGE, ReasonAction::Ignore),
+    // the matching RENAME_NEW_NAME record carries the name that replaces it
+    (WinUsnChanges::RENAME_OLD_NAME, ReasonAction::Ignore),
+    (WinUsnChanges::RENAME_NEW_NAME, ReasonAction::Update),
+    (WinUsnChanges::INDEXABLE_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::BASIC_INFO_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::HARD_LINK_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::COMPRESSION_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::ENCRYPTION_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::OBJECT_ID_CHANGE, ReasonAction::Ignore),
+    (WinUsnChanges::REPARSE_POINT_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::STREAM_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::TRANSACTED_CHANGE, ReasonAction::Ignore),
+    (WinUsnChanges::INTEGRITY_CHANGE, ReasonAction::Update),
+    (WinUsnChanges::DESIRED_STORAGE_CLASS_CHANGE, ReasonAction::Ignore),
+    (WinUsnChanges::CLOSE, ReasonAction::Ignore),
+];
+
+/// The USN to read from next and the records of a buffer filled by the journal. A record that can't be
+/// parsed goes to `errors`, the ones after it are still read as long as its length stays inside the buffer.
+pub fn usn_records(buffer: &[u8], errors: &mut Vec<ParseError>) -> Result<(i64, Vec<UsnRecord>), ParseError> {
+    let next_usn = read_i64(buffer, 0)?;
+    let mut records = Vec::new();
+    let mut offset = 8;
+    while offset < buffer.len() {
+        match UsnRecord::new(&buffer[offset..]) {
+            Ok(record) => {
+                offset += record.length;
+                records.push(record);
+            }
+            Err(e) => {
+                errors.push(e.offset_by(offset));
+                match read_u32(buffer, offset) {
+                    Ok(length) if length >= 8 && offset + length as usize <= buffer.len() => offset += length as usize,
+                    _ => break,
+                }
+            }
+        }
     }
+    Ok((next_usn, records))
+}
+
+fn reason_action(reason: WinUsnChanges) -> ReasonAction {
+    REASON_ACTIONS.iter()
+        .filter(|&&(bit, _)| reason.contains(bit))
+        .map(|&(_, action)| action)
+        .max()
+        .unwrap_or(ReasonAction::Ignore)
 }
 
 impl UsnRecord {
-    pub fn new(input: &[u8]) -> Result<Self, Error> {
-        let length = LittleEndian::read_u32(input) as usize;
-        let version = LittleEndian::read_u16(&input[4..]);
-        let (seq_number, fr_number, parent_fr_number) = match version {
-            2 => {
-                let seq_number = LittleEndian::read_u16(&input[14..]);
-                let fr = LittleEndian::read_i64(&input[8..]);
-                let parent_fr = LittleEndian::read_i64(&input[16..]);
-                (seq_number, fr, parent_fr)
-            }
-            _ => Err(UsnRecordVersionUnsupported(version))?
+    pub fn new(input: &[u8]) -> Result<Self, ParseError> {
+        let length = read_u32(input, 0)? as usize;
+        let version = read_u16(input, 4)?;
+        let min_length = match version {
+            2 => USN_RECORD_V2_SIZE,
+            3 => USN_RECORD_V3_SIZE,
+            RANGE_TRACKING_VERSION => USN_RECORD_V4_SIZE,
+            _ => Err(ParseError::new(4, UnsupportedVersion(version)))?
+        };
+        if length < min_length || length > input.len() {
+            Err(ParseError::new(0, InvalidLength(length)))?
+        }
+        let input = &input[..length];
+        let mut record = match version {
+            2 => UsnRecord::v2(input)?,
+            3 => UsnRecord::v3(input)?,
+            _ => UsnRecord::v4(input)?,
         };
-        let mft_id = fr_number as u32;
-        let usn = LittleEndian::read_i64(&input[24..]);
-        let reason = LittleEndian::read_u32(&input[40..]);
-        let flags = LittleEndian::read_u32(&input[52..]);
-        let name_length = LittleEndian::read_u16(&input[56..]) as usize;
-        let name_offset = LittleEndian::read_u16(&input[58..]) as usize;
-        let name = windows_string(&input[name_offset..name_offset + name_length]);
-        Ok(UsnRecord { mft_id, fr_number, seq_number, parent_fr_number, reason, name, flags, length, usn })
+        record.version = version;
+        record.length = length;
+        Ok(record)
+    }
+
+    fn v2(input: &[u8]) -> Result<Self, ParseError> {
+        let mut record = UsnRecord::with_ids(read_u64(input, 0x08)? as u128, read_u64(input, 0x10)? as u128);
+        record.usn = read_i64(input, 0x18)?;
+        record.reason = read_u32(input, 0x28)?;
+        record.flags = read_u32(input, 0x34)?;
+        record.name = record_name(input, 0x38, record.fr_number)?;
+        Ok(record)
+    }
+
+    fn v3(input: &[u8]) -> Result<Self, ParseError> {
+        let mut record = UsnRecord::with_ids(read_u128(input, 0x08)?, read_u128(input, 0x18)?);
+        record.usn = read_i64(input, 0x28)?;
+        record.reason = read_u32(input, 0x38)?;
+        record.flags = read_u32(input, 0x44)?;
+        record.name = record_name(input, 0x48, record.fr_number)?;
+        Ok(record)
+    }
+
+    /// Range tracking records only describe modified extents, they have no name nor attributes.
+    fn v4(input: &[u8]) -> Result<Self, ParseError> {
+        let mut record = UsnRecord::with_ids(read_u128(input, 0x08)?, read_u128(input, 0x18)?);
+        record.usn = read_i64(input, 0x28)?;
+        record.reason = read_u32(input, 0x30)?;
+        Ok(record)
+    }
+
+    /// On NTFS the low 64 bits of a file id are the file reference number.
+    fn with_ids(file_id: u128, parent_file_id: u128) -> Self {
+        let fr_number = file_id as i64;
+        UsnRecord {
+            file_id,
+            parent_file_id,
+            fr_number,
+            mft_id: fr_number as u64 & RECORD_NUMBER_MASK,
+            seq_number: (fr_number >> 48) as u16,
+            parent_fr_number: parent_file_id as i64,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_range_tracking(&self) -> bool {
+        self.version == RANGE_TRACKING_VERSION
     }
 
     pub fn into_change(self, entry: FileRecord) -> UsnChange {
         use self::UsnChange::*;
         let change = WinUsnChanges::from_bits_truncate(self.reason);
-        if change == WinUsnChanges::CLOSE {
-            return IGNORE;
-        }
-        if entry.fr_number() != self.fr_number && !change.contains(WinUsnChanges::FILE_DELETE) {
+        if self.is_range_tracking() {
             return IGNORE;
         }
         if change.contains(WinUsnChanges::FILE_DELETE | WinUsnChanges::FILE_CREATE) {
             return IGNORE;
         }
-        if change.contains(WinUsnChanges::FILE_DELETE) {
-            return match self.flags {
-                0x16 => DELETE(self),
-                0x30 => DELETE(self),
-                _ => IGNORE,
-            };
+        let action = reason_action(change);
+        if entry.fr_number() != self.fr_number && action != ReasonAction::Delete {
+            return IGNORE;
         }
-        if change.contains(WinUsnChanges::FILE_CREATE) {
-            return NEW(entry);
+        match action {
+            ReasonAction::Delete => DELETE(self),
+            ReasonAction::Create => NEW(entry),
+            ReasonAction::Update => UPDATE(entry),
+            ReasonAction::Ignore => IGNORE,
         }
-        if change.contains(WinUsnChanges::BASIC_INFO_CHANGE & WinUsnChanges::RENAME_NEW_NAME) {
-            return UPDATE(entry);
+    }
+
+    /// Pairs the RENAME_NEW_NAME record with the RENAME_OLD_NAME record `old` of the same file.
+    fn into_rename(self, old: UsnRecord, entry: FileRecord) -> UsnChange {
+        use self::UsnChange::*;
+        let new_parent = self.parent_fr_number;
+        let new_name = self.name.clone();
+        match self.into_change(entry) {
+            UPDATE(file) => {
+                let rename = UsnRename {
+                    old_parent: old.parent_fr_number,
+                    old_name: old.name,
+                    new_parent,
+                    new_name,
+                    file,
+                };
+                if rename.old_parent != rename.new_parent {
+                    MOVE(rename)
+                } else if rename.old_name != rename.new_name {
+                    RENAME(rename)
+                } else {
+                    UPDATE(rename.file)
+                }
+            }
+            change => change,
         }
-        unreachable!()
     }
 
     pub fn is_dir(&self) -> bool {
-        self.flags == 0x16
+        self.flags & FILE_ATTRIBUTE_DIRECTORY != 0
+    }
+}
+
+/// Turns a batch of records, in journal order, into changes. Every change of a file is written as it
+/// happens and once more, with all the reasons of the session, when the file is closed; only the close
+/// record is applied. Renames are the exception: the RENAME_OLD_NAME record of a file is held in
+/// `old_names`, which outlives the batch, until its RENAME_NEW_NAME record shows up.
+pub fn into_changes(records: Vec<(UsnRecord, FileRecord)>, old_names: &mut HashMap<i64, UsnRecord>) -> Vec<UsnChange> {
+    let mut changes = Vec::with_capacity(records.len());
+    for (record, entry) in records {
+        let reason = WinUsnChanges::from_bits_truncate(record.reason);
+        if reason.contains(WinUsnChanges::RENAME_OLD_NAME) && !reason.contains(WinUsnChanges::RENAME_NEW_NAME) {
+            // a file created in the same session isn't indexed yet, its close record adds it under the last name
+            if !reason.contains(WinUsnChanges::FILE_CREATE) {
+                old_names.insert(record.fr_number, record);
+            }
+            continue;
+        }
+        if reason.contains(WinUsnChanges::RENAME_NEW_NAME) {
+            if let Some(old) = old_names.remove(&record.fr_number) {
+                changes.push(record.into_rename(old, entry));
+                continue;
+            }
+        }
+        if reason.contains(WinUsnChanges::CLOSE) {
+            old_names.remove(&record.fr_number);
+            changes.push(record.into_change(entry));
+        }
     }
+    changes
+}
+
+fn record_name(input: &[u8], offset: usize, fr_number: i64) -> Result<String, ParseError> {
+    let name_length = read_u16(input, offset)? as usize;
+    let name_offset = read_u16(input, offset + 2)? as usize;
+    slice(input, name_offset, name_length)
+        .map(windows_string)
+        .map_err(|e| e.in_record(fr_number as u64 & RECORD_NUMBER_MASK))
 }
 
 #[cfg(test)]
 mod tests {
+    use byteorder::{
+        ByteOrder,
+        LittleEndian,
+    };
     use super::*;
     use super::UsnChange::*;
 
     fn new_record(change_reason: WinUsnChanges) -> UsnRecord {
         UsnRecord {
+            version: 2,
+            file_id: 0,
+            parent_file_id: 0,
             fr_number: 0,
             mft_id: 0,
             seq_number: 0,
             parent_fr_number: 0,
             reason: change_reason.bits(),
-            flags: 0x30,
+            // FILE_ATTRIBUTE_ARCHIVE
+            flags: 0x20,
             usn: 0,
             length: 0,
             name: "name".to_owned(),
         }
     }
 
+    #[test]
+    fn usn_record_rejects_truncated_input() {
+        let mut input = [0u8; 0x48];
+        input[0] = 0x50;
+        input[4] = 2;
+        assert_eq!(InvalidLength(0x50), UsnRecord::new(&input).unwrap_err().reason);
+        assert_eq!(OutOfBounds(4), UsnRecord::new(&input[..2]).unwrap_err().reason);
+
+        input[0] = 0x48;
+        input[56] = 0x10;
+        input[58] = 0x3C;
+        assert_eq!(OutOfBounds(0x10), UsnRecord::new(&input).unwrap_err().reason);
+    }
+
+    #[test]
+    fn usn_record_rejects_unknown_version() {
+        let mut input = [0u8; 0x48];
+        input[0] = 0x48;
+        input[4] = 5;
+        assert_eq!(UnsupportedVersion(5), UsnRecord::new(&input).unwrap_err().reason);
+    }
+
+    #[test]
+    fn usn_record_v3_with_128_bit_ids() {
+        let mut input = [0u8; 0x50];
+        LittleEndian::write_u32(&mut input[0x00..], 0x50);
+        LittleEndian::write_u16(&mut input[0x04..], 3);
+        LittleEndian::write_u64(&mut input[0x08..], 0x0005_0000_0000_0063);
+        LittleEndian::write_u64(&mut input[0x10..], 0x0000_0000_0000_0001);
+        LittleEndian::write_u64(&mut input[0x18..], 0x0001_0000_0000_0005);
+        LittleEndian::write_i64(&mut input[0x28..], 1234);
+        LittleEndian::write_u32(&mut input[0x38..], 0x100);
+        LittleEndian::write_u32(&mut input[0x44..], 0x20);
+        LittleEndian::write_u16(&mut input[0x48..], 4);
+        LittleEndian::write_u16(&mut input[0x4A..], 0x4C);
+        input[0x4C..0x50].copy_from_slice(&[0x61, 0, 0x62, 0]);
+
+        let record = UsnRecord::new(&input).unwrap();
+
+        assert_eq!(3, record.version);
+        assert_eq!(1 << 64 | 0x0005_0000_0000_0063, record.file_id);
+        assert_eq!(99, record.mft_id);
+        assert_eq!(5, record.seq_number);
+        assert_eq!(0x0001_0000_0000_0005, record.parent_fr_number);
+        assert_eq!(1234, record.usn);
+        assert_eq!(0x100, record.reason);
+        assert_eq!(0x20, record.flags);
+        assert_eq!("ab", record.name);
+        assert_eq!(0x50, record.length);
+    }
+
+    fn v2_input(fr_number: u64, name: &str) -> Vec<u8> {
+        let length = (USN_RECORD_V2_SIZE + 2 * name.len() + 7) / 8 * 8;
+        let mut input = vec![0u8; length];
+        LittleEndian::write_u32(&mut input[0x00..], length as u32);
+        LittleEndian::write_u16(&mut input[0x04..], 2);
+        LittleEndian::write_u64(&mut input[0x08..], fr_number);
+        LittleEndian::write_u16(&mut input[0x38..], 2 * name.len() as u16);
+        LittleEndian::write_u16(&mut input[0x3A..], 0x3C);
+        for (i, c) in name.bytes().enumerate() {
+            input[0x3C + 2 * i] = c;
+        }
+        input
+    }
+
+    #[test]
+    fn corrupt_record_is_skipped() {
+        let mut corrupt = v2_input(2, "bad");
+        LittleEndian::write_u16(&mut corrupt[0x04..], 9);
+        let mut buffer = vec![0u8; 8];
+        LittleEndian::write_i64(&mut buffer, 4096);
+        buffer.extend(v2_input(1, "a"));
+        buffer.extend(&corrupt);
+        buffer.extend(v2_input(3, "c"));
+        let mut errors = Vec::new();
+
+        let (next_usn, records) = usn_records(&buffer, &mut errors).unwrap();
+
+        assert_eq!(4096, next_usn);
+        assert_eq!(vec!["a", "c"], records.iter().map(|r| r.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(vec![ParseError::new(8 + 0x40 + 4, UnsupportedVersion(9))], errors);
+    }
+
+    #[test]
+    fn records_after_a_bad_length_are_dropped() {
+        let mut corrupt = v2_input(2, "bad");
+        LittleEndian::write_u32(&mut corrupt[0x00..], 0x1000);
+        let mut buffer = vec![0u8; 8];
+        buffer.extend(v2_input(1, "a"));
+        buffer.extend(&corrupt);
+        buffer.extend(v2_input(3, "c"));
+        let mut errors = Vec::new();
+
+        let (_, records) = usn_records(&buffer, &mut errors).unwrap();
+
+        assert_eq!(1, records.len());
+        assert_eq!(InvalidLength(0x1000), errors[0].reason);
+    }
+
+    #[test]
+    fn usn_record_v4_is_ignored() {
+        let mut input = [0u8; 0x60];
+        LittleEndian::write_u32(&mut input[0x00..], 0x60);
+        LittleEndian::write_u16(&mut input[0x04..], 4);
+        LittleEndian::write_u64(&mut input[0x08..], 99);
+        LittleEndian::write_u32(&mut input[0x30..], WinUsnChanges::FILE_DELETE.bits());
+
+        let record = UsnRecord::new(&input).unwrap();
+
+        assert!(record.is_range_tracking());
+        assert_eq!(0x60, record.length);
+        assert_eq!(IGNORE, record.into_change(FileRecord::default()));
+    }
+
     #[test]
     fn usn_record_ignore_close_only() {
         let record = new_record(WinUsnChanges::CLOSE);
@@ -117,7 +437,6 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn usn_record_to_update() {
         let mut record = new_record(WinUsnChanges::BASIC_INFO_CHANGE);
         let change = UPDATE(FileRecord::default());
@@ -136,6 +455,131 @@ mod tests {
         assert_eq!(change, record.into_change(FileRecord::default()));
     }
 
+    #[test]
+    fn usn_record_every_reason_has_an_action() {
+        let mapped = REASON_ACTIONS.iter().fold(WinUsnChanges::empty(), |acc, &(bit, _)| acc | bit);
+        assert_eq!(WinUsnChanges::all(), mapped);
+    }
+
+    #[test]
+    fn usn_record_content_changes_to_update() {
+        let change = UPDATE(FileRecord::default());
+        for &reason in &[WinUsnChanges::DATA_OVERWRITE, WinUsnChanges::DATA_EXTEND, WinUsnChanges::DATA_TRUNCATION, WinUsnChanges::HARD_LINK_CHANGE,
+            WinUsnChanges::STREAM_CHANGE, WinUsnChanges::REPARSE_POINT_CHANGE, WinUsnChanges::NAMED_DATA_EXTEND | WinUsnChanges::CLOSE] {
+            assert_eq!(change, new_record(reason).into_change(FileRecord::default()));
+        }
+    }
+
+    #[test]
+    fn usn_record_ignores_changes_not_indexed() {
+        for &reason in &[WinUsnChanges::SECURITY_CHANGE, WinUsnChanges::RENAME_OLD_NAME,
+            WinUsnChanges::OBJECT_ID_CHANGE, WinUsnChanges::EA_CHANGE | WinUsnChanges::CLOSE] {
+            assert_eq!(IGNORE, new_record(reason).into_change(FileRecord::default()));
+        }
+    }
+
+    #[test]
+    fn usn_record_create_wins_over_update() {
+        let record = new_record(WinUsnChanges::FILE_CREATE | WinUsnChanges::DATA_EXTEND | WinUsnChanges::CLOSE);
+        assert_eq!(NEW(FileRecord::default()), record.into_change(FileRecord::default()));
+    }
+
+    fn session(reasons: &[WinUsnChanges], parent: i64, name: &str) -> Vec<(UsnRecord, FileRecord)> {
+        reasons.iter()
+            .map(|&reason| {
+                let mut record = new_record(reason);
+                record.parent_fr_number = parent;
+                record.name = name.to_owned();
+                (record, FileRecord::default())
+            })
+            .collect()
+    }
+
+    /// The records a rename writes: the old name on its own, then the new name as it happens and at close.
+    fn rename_records(old_parent: i64, old_name: &str, new_parent: i64, new_name: &str) -> Vec<(UsnRecord, FileRecord)> {
+        let mut records = session(&[WinUsnChanges::RENAME_OLD_NAME], old_parent, old_name);
+        records.extend(session(&[WinUsnChanges::RENAME_NEW_NAME, WinUsnChanges::RENAME_NEW_NAME | WinUsnChanges::CLOSE], new_parent, new_name));
+        records
+    }
+
+    fn changes(records: Vec<(UsnRecord, FileRecord)>) -> Vec<UsnChange> {
+        into_changes(records, &mut HashMap::new())
+    }
+
+    #[test]
+    fn usn_records_paired_into_rename() {
+        let expected = RENAME(UsnRename {
+            old_parent: 5,
+            old_name: "old".to_owned(),
+            new_parent: 5,
+            new_name: "new".to_owned(),
+            file: FileRecord::default(),
+        });
+        assert_eq!(vec![expected, UPDATE(FileRecord::default())], changes(rename_records(5, "old", 5, "new")));
+    }
+
+    #[test]
+    fn usn_records_paired_into_move() {
+        let expected = MOVE(UsnRename {
+            old_parent: 5,
+            old_name: "name".to_owned(),
+            new_parent: 6,
+            new_name: "name".to_owned(),
+            file: FileRecord::default(),
+        });
+        assert_eq!(vec![expected, UPDATE(FileRecord::default())], changes(rename_records(5, "name", 6, "name")));
+    }
+
+    #[test]
+    fn usn_records_paired_across_batches() {
+        let mut old_names = HashMap::new();
+        let mut records = rename_records(5, "old", 5, "new");
+        let rest = records.split_off(1);
+
+        assert!(into_changes(records, &mut old_names).is_empty());
+        match into_changes(rest, &mut old_names).remove(0) {
+            RENAME(rename) => assert_eq!(("old", "new"), (rename.old_name.as_str(), rename.new_name.as_str())),
+            change => panic!("{:?}", change),
+        }
+        assert!(old_names.is_empty());
+    }
+
+    #[test]
+    fn usn_record_new_name_without_old_name_is_an_update() {
+        let mut records = rename_records(5, "old", 5, "new");
+        records.remove(0);
+        assert_eq!(vec![UPDATE(FileRecord::default())], changes(records));
+    }
+
+    #[test]
+    fn usn_record_old_name_is_dropped_at_close() {
+        let mut old_names = HashMap::new();
+        let mut records = session(&[WinUsnChanges::RENAME_OLD_NAME], 5, "old");
+        records.extend(session(&[WinUsnChanges::FILE_DELETE | WinUsnChanges::CLOSE], 5, "old"));
+        let deleted = records[1].0.clone();
+
+        assert_eq!(vec![DELETE(deleted)], into_changes(records, &mut old_names));
+        assert!(old_names.is_empty());
+    }
+
+    #[test]
+    fn usn_records_applied_at_close() {
+        let create = session(&[WinUsnChanges::FILE_CREATE, WinUsnChanges::FILE_CREATE | WinUsnChanges::DATA_EXTEND,
+            WinUsnChanges::FILE_CREATE | WinUsnChanges::DATA_EXTEND | WinUsnChanges::CLOSE], 5, "new");
+        assert_eq!(vec![NEW(FileRecord::default())], changes(create));
+
+        let write = session(&[WinUsnChanges::DATA_EXTEND, WinUsnChanges::DATA_EXTEND | WinUsnChanges::DATA_OVERWRITE], 5, "open");
+        assert!(changes(write).is_empty());
+    }
+
+    #[test]
+    fn usn_records_of_new_file_renamed_at_once() {
+        let mut records = session(&[WinUsnChanges::FILE_CREATE, WinUsnChanges::FILE_CREATE | WinUsnChanges::RENAME_OLD_NAME], 5, "New Folder");
+        records.extend(session(&[WinUsnChanges::FILE_CREATE | WinUsnChanges::RENAME_NEW_NAME,
+            WinUsnChanges::FILE_CREATE | WinUsnChanges::RENAME_NEW_NAME | WinUsnChanges::CLOSE], 5, "docs"));
+        assert_eq!(vec![NEW(FileRecord::default())], changes(records));
+    }
+
     #[test]
     fn usn_record_to_delete_file() {
         let mut record = new_record(WinUsnChanges::FILE_DELETE);
@@ -151,21 +595,27 @@ mod tests {
     fn usn_record_to_delete_dir() {
         let mut record = new_record(WinUsnChanges::FILE_DELETE);
         record.mft_id = 99;
-        record.flags = 0x16;
+        record.flags = 0x10;
+        assert!(record.is_dir());
         assert_eq!(DELETE(record.clone()), record.into_change(FileRecord::default()));
 
         record = new_record(WinUsnChanges::FILE_DELETE | WinUsnChanges::CLOSE);
         record.mft_id = 99;
         record.flags = 0x16;
+        assert!(record.is_dir());
         assert_eq!(DELETE(record.clone()), record.into_change(FileRecord::default()));
     }
 
     #[test]
-    fn usn_record_ignores_deleted_other_than_dir_or_file() {
-        let mut record = new_record(WinUsnChanges::FILE_DELETE);
-        record.mft_id = 99;
-        record.flags = 0x20;
-        assert_eq!(IGNORE, record.into_change(FileRecord::default()));
+    fn usn_record_deletes_whatever_the_attributes() {
+        // NORMAL, READONLY | ARCHIVE, HIDDEN | SYSTEM | ARCHIVE
+        for &flags in &[0x80, 0x21, 0x26] {
+            let mut record = new_record(WinUsnChanges::FILE_DELETE | WinUsnChanges::CLOSE);
+            record.mft_id = 99;
+            record.flags = flags;
+            assert!(!record.is_dir());
+            assert_eq!(DELETE(record.clone()), record.into_change(FileRecord::default()));
+        }
     }
 
     #[test]
@@ -1,70 +1,118 @@
-use byteorder::{
-    ByteOrder,
-    LittleEndian,
-};
 use ntfs::attributes::*;
+use ntfs::parse_error::{
+    ParseError,
+    read_u16,
+    read_u32,
+    read_u64,
+    slice,
+};
+use ntfs::parse_error::ParseErrorKind::*;
 use ntfs::volume_data::VolumeData;
 
 const DOS_NAMESPACE: u8 = 2;
+pub const RECORD_NUMBER_MASK: u64 = 0x0000_FFFF_FFFF_FFFF;
 
 #[derive(Debug, Default, PartialEq)]
 pub struct FileRecordHeader {
-    pub fr_number: u32,
+    pub fr_number: u64,
     pub seq_number: u16,
     pub flags: u16,
     pub base_record: u64,
+    pub torn: bool,
     fixup_seq: Vec<u8>,
     attr_offset: usize,
 }
 
-fn file_record_header(input: &[u8]) -> Option<FileRecordHeader> {
-    if input[..4] == b"FILE"[..] {
-        let fixup_offset = LittleEndian::read_u16(&input[0x4..]) as usize;
-        let fixup_size = LittleEndian::read_u16(&input[0x06..]) as usize;
-        let seq_number = LittleEndian::read_u16(&input[0x10..]) as u16;
-        let attr_offset = LittleEndian::read_u16(&input[0x14..]) as usize;
-        let flags = LittleEndian::read_u16(&input[0x16..]);
-        let base_record = LittleEndian::read_u64(&input[0x20..]);
-        let fr_number = LittleEndian::read_u32(&input[0x2C..]);
-        let fixup_seq = input[fixup_offset..fixup_offset + 2 * fixup_size].to_vec();
-        Some(FileRecordHeader {
-            flags,
-            fr_number,
-            attr_offset,
-            seq_number,
-            fixup_seq,
-            base_record,
-        })
-    } else {
-        None
+/// What to do with a record whose sectors don't all end with the update sequence number.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FixupPolicy {
+    Reject,
+    Warn,
+}
+
+fn file_record_header(input: &[u8]) -> Result<Option<FileRecordHeader>, ParseError> {
+    if slice(input, 0, 4)? != &b"FILE"[..] {
+        return Ok(None);
+    }
+    // the record number is 48 bits, its upper 16 bits come before the lower 32
+    let fr_number = read_u32(input, 0x2C)? as u64 | (read_u16(input, 0x2A)? as u64) << 32;
+    header_fields(input, fr_number).map(Some).map_err(|e| e.in_record(fr_number))
+}
+
+fn header_fields(input: &[u8], fr_number: u64) -> Result<FileRecordHeader, ParseError> {
+    let fixup_offset = read_u16(input, 0x4)? as usize;
+    let fixup_size = read_u16(input, 0x06)? as usize;
+    let seq_number = read_u16(input, 0x10)?;
+    let attr_offset = read_u16(input, 0x14)? as usize;
+    let flags = read_u16(input, 0x16)?;
+    let base_record = read_u64(input, 0x20)?;
+    let fixup_seq = slice(input, fixup_offset, 2 * fixup_size)?.to_vec();
+    if attr_offset > input.len() {
+        return Err(ParseError::new(0x14, InvalidLength(attr_offset)));
+    }
+    Ok(FileRecordHeader {
+        flags,
+        fr_number,
+        attr_offset,
+        seq_number,
+        fixup_seq,
+        base_record,
+        torn: false,
+    })
+}
+
+/// Restores the sector tails from the fixup array.
+/// Returns the offset of the first tail that didn't hold the update sequence number.
+fn apply_fixups(buffer: &mut [u8], fixup_seq: &[u8], bytes_per_sector: usize) -> Result<Option<usize>, ParseError> {
+    let (usn, fixups) = fixup_seq.split_at(fixup_seq.len().min(2));
+    let mut torn = None;
+    for (i, fixup) in fixups.chunks(2).enumerate() {
+        let tail = bytes_per_sector * (i + 1) - 2;
+        if tail + 2 > buffer.len() {
+            return Err(ParseError::new(tail, OutOfBounds(2)));
+        }
+        if torn.is_none() && &buffer[tail..tail + 2] != usn {
+            torn = Some(tail);
+        }
+        buffer[tail..tail + 2].copy_from_slice(fixup);
     }
+    Ok(torn)
 }
 
 #[derive(Default, Debug, PartialEq)]
 pub struct FileRecord {
     pub data_attr: DataAttr,
+    pub attribute_list: Option<AttributeListAttr>,
+    pub reparse_attr: Option<ReparseAttr>,
     pub name_attrs: Vec<FilenameAttr>,
+    pub streams: Vec<StreamAttr>,
     pub standard_attr: StandardAttr,
     pub header: FileRecordHeader,
 }
 
 impl FileRecord {
-    pub fn parse_mft_entry(buffer: &mut [u8], volume_data: VolumeData) -> Option<FileRecord> {
-        match file_record_header(buffer) {
-            Some(header) => {
-                for (i, chunk) in header.fixup_seq.chunks(2).skip(1).enumerate() {
-                    buffer[volume_data.bytes_per_sector as usize * (i + 1) - 2] = *chunk.first().unwrap();
-                    buffer[volume_data.bytes_per_sector as usize * (i + 1) - 1] = *chunk.last().unwrap();
+    pub fn parse_mft_entry(buffer: &mut [u8], volume_data: VolumeData, policy: FixupPolicy) -> Result<Option<FileRecord>, ParseError> {
+        match file_record_header(buffer)? {
+            Some(mut header) => {
+                let record = header.fr_number;
+                let torn = apply_fixups(buffer, &header.fixup_seq, volume_data.bytes_per_sector as usize)
+                    .map_err(|e| e.in_record(record))?;
+                if let Some(offset) = torn {
+                    if policy == FixupPolicy::Reject {
+                        return Err(ParseError::new(offset, TornRecord).in_record(record));
+                    }
+                    header.torn = true;
                 }
-                let attributes = parse_attributes(&buffer[header.attr_offset as usize..], DATA);
-                Some(FileRecord::new(attributes, header))
+                let attributes = parse_attributes(&buffer[header.attr_offset..], REPARSE_POINT)
+                    .map_err(|e| e.offset_by(header.attr_offset).in_record(record))?;
+                FileRecord::new(attributes, header).map(Some)
             }
-            _ => None
+            _ => Ok(None)
         }
     }
 
     pub fn is_unused(&self) -> bool {
-        self.header.flags % 2 == 0 || self.name_attrs.is_empty()
+        !self.is_in_use() || self.name_attrs.is_empty()
     }
 
     pub fn is_directory(&self) -> bool {
@@ -75,42 +123,164 @@ impl FileRecord {
         !(self.name_attrs.len() == 1 && self.name_attrs[0].namespace == DOS_NAMESPACE)
     }
 
-    pub fn is_candidate_for_fixes(&self) -> bool {
-        self.header.base_record != 0 && self.is_directory()
+    pub fn is_in_use(&self) -> bool {
+        self.header.flags & 0x01 != 0
+    }
+
+    pub fn is_extension(&self) -> bool {
+        self.header.base_record != 0
+    }
+
+    pub fn base_record_number(&self) -> u64 {
+        self.header.base_record & RECORD_NUMBER_MASK
+    }
+
+    pub fn has_attribute_list(&self) -> bool {
+        self.attribute_list.is_some()
+    }
+
+    /// The other records holding attributes of this one, as listed by its attribute list.
+    /// A non resident list has to be loaded first, see `volume_source::load_attribute_list`.
+    pub fn extension_records(&self) -> Vec<u64> {
+        let record = self.header.fr_number;
+        let mut records = self.attribute_list.iter()
+            .flat_map(|list| list.entries.iter())
+            .map(|entry| entry.base_reference & RECORD_NUMBER_MASK)
+            .filter(|&number| number != record)
+            .collect::<Vec<_>>();
+        records.sort();
+        records.dedup();
+        records
     }
 
-    pub fn requires_name_fix(&self) -> bool {
-        self.is_directory() && !self.has_name() && self.header.base_record == 0
+    /// Moves the attributes of an extension record into its base record.
+    pub fn merge(&mut self, extension: FileRecord) {
+        self.name_attrs.extend(extension.name_attrs);
+        self.streams.extend(extension.streams);
+        if extension.reparse_attr.is_some() {
+            self.reparse_attr = extension.reparse_attr;
+        }
+        if self.data_attr.is_empty() {
+            self.data_attr = extension.data_attr;
+        } else if !extension.data_attr.is_empty() {
+            self.data_attr.merge(extension.data_attr);
+        }
     }
 
     pub fn fr_number(&self) -> i64 {
-        self.header.fr_number as i64 | (self.header.seq_number as i64) << 48
+        (self.header.fr_number | (self.header.seq_number as u64) << 48) as i64
     }
 
-    pub fn new(attrs: Vec<Attribute>, header: FileRecordHeader) -> Self {
-        let mut result = FileRecord::default();
-        result.header = header;
+    pub fn new(attrs: Vec<Attribute>, header: FileRecordHeader) -> Result<Self, ParseError> {
+        let record = header.fr_number;
+        let mut entry = FileRecord::default();
+        entry.header = header;
         let mut standard_count = 0;
+        let mut list_count = 0;
         let mut data_count = 0;
+        let mut reparse_count = 0;
         //TODO handle attribute flags (e.g: sparse or compressed)
-        let entry = attrs.into_iter().fold(result, |mut acc, attr| {
+        for attr in attrs {
             match attr.attr_type {
                 AttributeType::Standard(val) => {
                     standard_count += 1;
-                    acc.standard_attr = val;
+                    entry.standard_attr = val;
+                }
+                AttributeType::AttributeList(val) => {
+                    list_count += 1;
+                    entry.attribute_list = Some(val);
                 }
                 AttributeType::Filename(val) => {
-                    acc.name_attrs.push(val);
+                    entry.name_attrs.push(val);
                 }
                 AttributeType::Data(val) => {
                     data_count += 1;
-                    acc.data_attr = val;
+                    entry.data_attr = val;
+                }
+                AttributeType::Stream(val) => {
+                    entry.streams.push(val);
+                }
+                AttributeType::ReparsePoint(val) => {
+                    reparse_count += 1;
+                    entry.reparse_attr = Some(val);
                 }
             }
-            acc
-        });
-        assert!(1 >= standard_count, "Record {} got {} standard_attr", entry.fr_number() as u32, standard_count);
-        assert!(1 >= data_count, "Record {} got {} data_attr", entry.fr_number() as u32, data_count);
-        entry
+        }
+        let duplicate = if standard_count > 1 {
+            Some(STANDARD)
+        } else if list_count > 1 {
+            Some(ATTRIBUTE_LIST)
+        } else if data_count > 1 {
+            Some(DATA)
+        } else if reparse_count > 1 {
+            Some(REPARSE_POINT)
+        } else {
+            None
+        };
+        match duplicate {
+            Some(attr_type) => Err(ParseError::new(entry.header.attr_offset, DuplicateAttribute(attr_type)).in_record(record)),
+            None => Ok(entry),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use byteorder::{
+        ByteOrder,
+        LittleEndian,
+    };
+    use super::*;
+
+    const VOLUME_DATA: VolumeData = VolumeData { mft_start_lcn: 4, mft_mirror_lcn: 2, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
+
+    fn record_buffer() -> Vec<u8> {
+        let mut buffer = vec![0u8; 1024];
+        buffer[..4].copy_from_slice(b"FILE");
+        LittleEndian::write_u16(&mut buffer[0x04..], 0x30);
+        LittleEndian::write_u16(&mut buffer[0x06..], 3);
+        LittleEndian::write_u16(&mut buffer[0x14..], 0x38);
+        LittleEndian::write_u16(&mut buffer[0x16..], 0x01);
+        LittleEndian::write_u32(&mut buffer[0x2C..], 7);
+        buffer[0x30..0x36].copy_from_slice(&[0x05, 0x00, 0xAA, 0xAA, 0xBB, 0xBB]);
+        buffer[0x38..0x3C].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        buffer[510..512].copy_from_slice(&[0x05, 0x00]);
+        buffer[1022..1024].copy_from_slice(&[0x05, 0x00]);
+        buffer
+    }
+
+    #[test]
+    fn fixups_are_restored() {
+        let mut buffer = record_buffer();
+        let entry = FileRecord::parse_mft_entry(&mut buffer, VOLUME_DATA, FixupPolicy::Reject).unwrap().unwrap();
+        assert!(!entry.header.torn);
+        assert_eq!([0xAA, 0xAA], buffer[510..512]);
+        assert_eq!([0xBB, 0xBB], buffer[1022..1024]);
+    }
+
+    #[test]
+    fn torn_record_is_rejected() {
+        let mut buffer = record_buffer();
+        buffer[1022] = 0x04;
+        let error = FileRecord::parse_mft_entry(&mut buffer, VOLUME_DATA, FixupPolicy::Reject).unwrap_err();
+        assert_eq!(ParseError::new(1022, TornRecord).in_record(7), error);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn torn_record_is_flagged() {
+        let mut buffer = record_buffer();
+        buffer[510] = 0x04;
+        let entry = FileRecord::parse_mft_entry(&mut buffer, VOLUME_DATA, FixupPolicy::Warn).unwrap().unwrap();
+        assert!(entry.header.torn);
+        assert_eq!(7, entry.header.fr_number);
+    }
+
+    #[test]
+    fn record_number_has_48_bits() {
+        let mut buffer = record_buffer();
+        LittleEndian::write_u16(&mut buffer[0x10..], 3);
+        LittleEndian::write_u16(&mut buffer[0x2A..], 0x0001);
+        let entry = FileRecord::parse_mft_entry(&mut buffer, VOLUME_DATA, FixupPolicy::Reject).unwrap().unwrap();
+        assert_eq!(0x0001_0000_0007, entry.header.fr_number);
+        assert_eq!(0x0003_0001_0000_0007, entry.fr_number());
+    }
+}
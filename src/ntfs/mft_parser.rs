@@ -1,92 +1,166 @@
-use ntfs::file_record::FileRecord;
-use ntfs::FR_AT_ONCE;
-use ntfs::mft_reader::MftReader;
+use ntfs::file_record::{
+    FileRecord,
+    FixupPolicy,
+};
+use ntfs::parse_error::ParseError;
 use ntfs::volume_data::VolumeData;
 use slog::Logger;
 use std::collections::HashMap;
-use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use windows::async_io::{
-    BufferPool,
-    IOCompletionPort,
-    OutputOperation,
-};
+
+/// Records that couldn't be parsed, or were parsed but can't be trusted.
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub errors: Vec<ParseError>,
+    pub torn_records: Vec<u64>,
+    /// Records the volume failed to hand out, only the change journal asks for them one by one.
+    pub unreadable: Vec<u64>,
+}
 
 pub struct MftParser {
     logger: Logger,
     volume_data: VolumeData,
-    counter: Arc<AtomicUsize>,
-    pool: BufferPool,
-    iocp: Arc<IOCompletionPort>,
-    pub candidates: HashMap<i64, FileRecord>,
-    pub faulty: Vec<FileRecord>,
+    fixup_policy: FixupPolicy,
+    base_records: HashMap<u64, usize>,
+    extensions: HashMap<u64, Vec<FileRecord>>,
     pub files: Vec<FileRecord>,
+    pub report: ParseReport,
 }
 
 impl MftParser {
-    pub fn new(logger: Logger, mft: &FileRecord, volume_data: VolumeData) -> Self {
-        let counter = Arc::new(AtomicUsize::new(0));
-        let pool = BufferPool::new(16, FR_AT_ONCE as usize * volume_data.bytes_per_file_record as usize);
-        let iocp = Arc::new(IOCompletionPort::new(1).unwrap());
-
-        let candidates = HashMap::new();
-        let faulty = Vec::new();
+    pub fn new(logger: Logger, mft: &FileRecord, volume_data: VolumeData, fixup_policy: FixupPolicy) -> Self {
+        let base_records = HashMap::new();
+        let extensions = HashMap::new();
         let capacity = MftParser::estimate_capacity(&mft, &volume_data);
         info!(logger, "{:?}", volume_data; "estimated size" => capacity);
         let files = Vec::with_capacity(capacity);
-        MftParser { volume_data, counter, pool: pool.clone(), iocp: iocp.clone(), files, candidates, faulty, logger }
-    }
-    pub fn parse_iocp_buffer(&mut self) {
-        let mut operations_count = 0;
-        let mut finish = false;
-        let mut end = false;
-        while !end {
-            operations_count += 1;
-            let mut operation = self.iocp.get().unwrap();
-            if operation.completion_key() != 42 {
-                finish = true;
-            }
-            self.iocp_buffer_to_files(&mut operation);
-            self.pool.put(operation.into_buffer());
-            end = finish && operations_count == self.counter.load(Ordering::SeqCst);
-        }
-        self.fix_dir_hardlinks();
+        let report = ParseReport::default();
+        MftParser { volume_data, fixup_policy, files, base_records, extensions, report, logger }
     }
 
-    pub fn fix_dir_hardlinks(&mut self) {
-        assert_eq!(self.faulty.len(), self.candidates.len());
-        for mut f in self.faulty.drain(..) {
-            assert!(self.candidates.contains_key(&f.fr_number()));
-            let mut fix = self.candidates.remove(&f.fr_number()).unwrap();
-            f.name_attrs = fix.name_attrs;
-            self.files.push(f);
+    pub fn finish(&mut self) {
+        if !self.report.errors.is_empty() {
+            warn!(self.logger, "records failed to parse"; "count" => self.report.errors.len());
         }
+        if !self.report.torn_records.is_empty() {
+            warn!(self.logger, "torn records kept"; "count" => self.report.torn_records.len());
+        }
+        let orphans = self.extensions.drain().map(|(_, e)| e.len()).sum::<usize>();
+        if orphans > 0 {
+            warn!(self.logger, "extension records without base record"; "count" => orphans);
+        }
+        self.base_records.clear();
+        self.files.retain(|f| !f.is_unused());
     }
 
-    pub fn new_reader<P: AsRef<Path>>(&mut self, file: P, completion_key: usize) -> MftReader {
-        MftReader::new(self.pool.clone(), self.iocp.clone(), file, completion_key, self.counter.clone(), self.logger.clone())
-    }
     fn estimate_capacity(mft: &FileRecord, volume: &VolumeData) -> usize {
         let clusters = mft.data_attr.datarun.iter().map(|d| d.length_lcn as u32).sum::<u32>();
         (clusters * volume.bytes_per_cluster / volume.bytes_per_file_record) as usize
     }
 
-    fn iocp_buffer_to_files(&mut self, operation: &mut OutputOperation) {
-        let fr_count = operation.content_len();
-        for buff in operation.buffer_mut().chunks_mut(self.volume_data.bytes_per_file_record as usize).take(fr_count) {
-            if let Some(f) = FileRecord::parse_mft_entry(buff, self.volume_data) {
-                if f.is_unused() {
-                    continue;
+    pub fn parse_buffer(&mut self, buffer: &mut [u8], fr_count: usize) {
+        for buff in buffer.chunks_mut(self.volume_data.bytes_per_file_record as usize).take(fr_count) {
+            match FileRecord::parse_mft_entry(buff, self.volume_data, self.fixup_policy) {
+                Ok(Some(f)) => if f.is_in_use() {
+                    if f.header.torn {
+                        debug!(self.logger, "torn record"; "record" => f.header.fr_number);
+                        self.report.torn_records.push(f.header.fr_number);
+                    }
+                    self.add_record(f);
+                },
+                Ok(None) => {}
+                Err(e) => {
+                    debug!(self.logger, "{}", e);
+                    self.report.errors.push(e);
                 }
-                if f.requires_name_fix() {
-                    self.faulty.push(f);
-                } else if f.is_candidate_for_fixes() {
-                    self.candidates.insert(f.header.base_record as i64, f);
-                } else {
-                    self.files.push(f);
+            }
+        }
+    }
+
+    fn add_record(&mut self, mut f: FileRecord) {
+        if f.is_extension() {
+            let base = f.base_record_number();
+            match self.base_records.get(&base) {
+                Some(&pos) => self.files[pos].merge(f),
+                None => self.extensions.entry(base).or_insert_with(Vec::new).push(f),
+            }
+        } else {
+            if f.has_attribute_list() {
+                let record = f.header.fr_number;
+                for extension in self.extensions.remove(&record).unwrap_or_default() {
+                    f.merge(extension);
                 }
+                self.base_records.insert(record, self.files.len());
             }
+            self.files.push(f);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ntfs::attributes::{
+        AttributeListAttr,
+        FilenameAttr,
+    };
+    use slog::Discard;
+    use super::*;
+
+    fn parser() -> MftParser {
+        let volume_data = VolumeData { mft_start_lcn: 4, mft_mirror_lcn: 2, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
+        MftParser::new(Logger::root(Discard, o!()), &FileRecord::default(), volume_data, FixupPolicy::Reject)
+    }
+
+    fn base_record(fr_number: u64) -> FileRecord {
+        let mut file = FileRecord::default();
+        file.header.fr_number = fr_number;
+        file.header.flags = 0x01;
+        file.attribute_list = Some(AttributeListAttr::default());
+        file
+    }
+
+    fn extension_record(fr_number: u64, base: u64, name: &str, size: i64) -> FileRecord {
+        let mut file = FileRecord::default();
+        file.header.fr_number = fr_number;
+        file.header.flags = 0x01;
+        file.header.base_record = base | 3 << 48;
+        let mut name_attr = FilenameAttr::default();
+        name_attr.name = name.to_string();
+        file.name_attrs.push(name_attr);
+        file.data_attr.size = size;
+        file
+    }
+
+    #[test]
+    fn merges_extension_after_base() {
+        let mut parser = parser();
+        parser.add_record(base_record(10));
+        parser.add_record(extension_record(11, 10, "file.exe", 42));
+        parser.finish();
+
+        assert_eq!(1, parser.files.len());
+        assert_eq!("file.exe", parser.files[0].name_attrs[0].name);
+        assert_eq!(42, parser.files[0].data_attr.size);
+    }
+
+    #[test]
+    fn merges_extension_before_base() {
+        let mut parser = parser();
+        parser.add_record(extension_record(5, 10, "file.exe", 42));
+        parser.add_record(base_record(10));
+        parser.finish();
+
+        assert_eq!(1, parser.files.len());
+        assert_eq!("file.exe", parser.files[0].name_attrs[0].name);
+        assert_eq!(42, parser.files[0].data_attr.size);
+    }
+
+    #[test]
+    fn drops_records_without_names() {
+        let mut parser = parser();
+        parser.add_record(base_record(10));
+        parser.add_record(extension_record(11, 12, "orphan", 42));
+        parser.finish();
+
+        assert!(parser.files.is_empty());
+    }
+}
@@ -1,7 +1,9 @@
 use failure::Error;
 use ntfs::file_record::FileRecord;
 use ntfs::FR_AT_ONCE;
+use ntfs::mft_parser::MftParser;
 use ntfs::volume_data::VolumeData;
+use ntfs::volume_source::mft_chunks;
 use slog::Logger;
 use std::path::Path;
 use std::sync::Arc;
@@ -13,6 +15,43 @@ use windows::async_io::{
     IOCompletionPort,
 };
 
+pub struct IocpQueue {
+    pool: BufferPool,
+    logger: Logger,
+    iocp: Arc<IOCompletionPort>,
+    counter: Arc<AtomicUsize>,
+}
+
+impl IocpQueue {
+    pub fn new(volume_data: VolumeData, logger: Logger) -> Result<Self, Error> {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = BufferPool::new(16, FR_AT_ONCE as usize * volume_data.bytes_per_file_record as usize);
+        let iocp = Arc::new(IOCompletionPort::new(1)?);
+        Ok(IocpQueue { pool, iocp, counter, logger })
+    }
+
+    pub fn new_reader<P: AsRef<Path>>(&self, file: P, completion_key: usize) -> MftReader {
+        MftReader::new(self.pool.clone(), self.iocp.clone(), file, completion_key, self.counter.clone(), self.logger.clone())
+    }
+
+    pub fn parse_all(&mut self, parser: &mut MftParser) {
+        let mut operations_count = 0;
+        let mut finish = false;
+        let mut end = false;
+        while !end {
+            operations_count += 1;
+            let mut operation = self.iocp.get().unwrap();
+            if operation.completion_key() != 42 {
+                finish = true;
+            }
+            let fr_count = operation.content_len();
+            parser.parse_buffer(operation.buffer_mut(), fr_count);
+            self.pool.put(operation.into_buffer());
+            end = finish && operations_count == self.counter.load(Ordering::SeqCst);
+        }
+    }
+}
+
 pub struct MftReader {
     pool: BufferPool,
     logger: Logger,
@@ -44,30 +83,13 @@ impl MftReader {
     pub fn read_all(&mut self, mft: &FileRecord, volume_data: VolumeData) {
         use std::time::Instant;
         let now = Instant::now();
-        let mut absolute_lcn_offset = 0i64;
-        for (i, run) in mft.data_attr.datarun.iter().enumerate() {
-            absolute_lcn_offset += run.offset_lcn;
-            let absolute_offset = absolute_lcn_offset as u64 * volume_data.bytes_per_cluster as u64;
-            let mut file_record_count = run.length_lcn * volume_data.clusters_per_fr() as u64;
-
-            let full_runs_count = file_record_count / FR_AT_ONCE;
-            let partial_run_size = file_record_count % FR_AT_ONCE;
-            let datarun_info = o!("file count" => file_record_count, "full runs count" => full_runs_count, "full run size" => FR_AT_ONCE, "partial run size" => partial_run_size);
-            info!(&self.logger, "mft reader" ; "datarun" => i, "status" => "started", datarun_info);
-            for run in 0..full_runs_count {
-                let offset = absolute_offset + run * FR_AT_ONCE * volume_data.bytes_per_file_record as u64;
-                debug!(&self.logger, "mft reader - full run" ; "run" => run, "offset" => offset);
-                self.read(offset, FR_AT_ONCE as usize).unwrap();
-            }
-            if partial_run_size > 0 {
-                let offset = absolute_offset + full_runs_count * FR_AT_ONCE * volume_data.bytes_per_file_record as u64;
-                debug!(&self.logger, "mft reader - partial run" ; "run" => full_runs_count, "offset" => offset);
-                self.read(offset, partial_run_size as usize).unwrap();
-            }
-            info!(&self.logger, "mft reader"; "datarun" => i, "status" => "finished", "Time" => ?Instant::now().duration_since(now));
+        let chunks = mft_chunks(mft, volume_data);
+        info!(&self.logger, "mft reader" ; "status" => "started", "chunks count" => chunks.len(), "full run size" => FR_AT_ONCE);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            debug!(&self.logger, "mft reader - run" ; "run" => i, "offset" => chunk.offset, "file count" => chunk.fr_count);
+            self.read(chunk.offset, chunk.fr_count as usize).unwrap();
         }
         info!(&self.logger, "mft reader - finished";"Time" => ?Instant::now().duration_since(now));
         self.finish().unwrap();
     }
 }
-
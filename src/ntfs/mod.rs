@@ -6,6 +6,9 @@ mod windows_api;
 pub mod parse_operation;
 pub mod attributes;
 pub mod change_journal;
+pub mod volume_source;
+pub mod parse_error;
+pub mod volumes;
 
 
 //TODO make this value 'smart' depending on the HD
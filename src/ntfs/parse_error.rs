@@ -0,0 +1,92 @@
+use byteorder::{
+    ByteOrder,
+    LittleEndian,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Fail)]
+pub enum ParseErrorKind {
+    #[fail(display = "read of {} bytes past the end of the buffer", _0)]
+    OutOfBounds(usize),
+    #[fail(display = "invalid length {}", _0)]
+    InvalidLength(usize),
+    #[fail(display = "attribute 0x{:X} found more than once", _0)]
+    DuplicateAttribute(u32),
+    #[fail(display = "UsnRecord v{} is not supported", _0)]
+    UnsupportedVersion(u16),
+    #[fail(display = "sector does not end with the update sequence number")]
+    TornRecord,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Fail)]
+#[fail(display = "Failed to parse record {} at offset 0x{:X}: {}", record, offset, reason)]
+pub struct ParseError {
+    pub record: u64,
+    pub offset: usize,
+    pub reason: ParseErrorKind,
+}
+
+impl ParseError {
+    pub fn new(offset: usize, reason: ParseErrorKind) -> Self {
+        ParseError { record: 0, offset, reason }
+    }
+
+    pub fn in_record(mut self, record: u64) -> Self {
+        self.record = record;
+        self
+    }
+
+    pub fn offset_by(mut self, base: usize) -> Self {
+        self.offset += base;
+        self
+    }
+}
+
+pub fn slice(input: &[u8], offset: usize, len: usize) -> Result<&[u8], ParseError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= input.len() => Ok(&input[offset..end]),
+        _ => Err(ParseError::new(offset, ParseErrorKind::OutOfBounds(len))),
+    }
+}
+
+pub fn read_u8(input: &[u8], offset: usize) -> Result<u8, ParseError> {
+    slice(input, offset, 1).map(|b| b[0])
+}
+
+pub fn read_u16(input: &[u8], offset: usize) -> Result<u16, ParseError> {
+    slice(input, offset, 2).map(LittleEndian::read_u16)
+}
+
+pub fn read_u32(input: &[u8], offset: usize) -> Result<u32, ParseError> {
+    slice(input, offset, 4).map(LittleEndian::read_u32)
+}
+
+pub fn read_u64(input: &[u8], offset: usize) -> Result<u64, ParseError> {
+    slice(input, offset, 8).map(LittleEndian::read_u64)
+}
+
+pub fn read_u128(input: &[u8], offset: usize) -> Result<u128, ParseError> {
+    slice(input, offset, 16).map(|b| (LittleEndian::read_u64(&b[8..]) as u128) << 64 | LittleEndian::read_u64(b) as u128)
+}
+
+pub fn read_i64(input: &[u8], offset: usize) -> Result<i64, ParseError> {
+    slice(input, offset, 8).map(LittleEndian::read_i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_inside_bounds() {
+        let input = [1, 0, 2, 0, 0, 0];
+        assert_eq!(Ok(1), read_u16(&input, 0));
+        assert_eq!(Ok(2), read_u32(&input, 2));
+    }
+
+    #[test]
+    fn reads_past_the_end_fail() {
+        let input = [1, 0, 2, 0, 0, 0];
+        assert_eq!(Err(ParseError::new(4, ParseErrorKind::OutOfBounds(4))), read_u32(&input, 4));
+        assert_eq!(Err(ParseError::new(usize::max_value(), ParseErrorKind::OutOfBounds(2))), slice(&input, usize::max_value(), 2));
+    }
+}
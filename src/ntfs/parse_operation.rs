@@ -1,51 +1,102 @@
 use failure::Error;
-use ntfs::file_record::FileRecord;
-use ntfs::mft_parser::MftParser;
-use ntfs::volume_data::VolumeData;
-use ntfs::windows_api::get_volume_data;
+use ntfs::change_journal::{
+    current_journal,
+    JournalState,
+};
+use ntfs::file_record::{
+    FileRecord,
+    FixupPolicy,
+};
+use ntfs::mft_parser::{
+    MftParser,
+    ParseReport,
+};
+use ntfs::mft_reader::IocpQueue;
+use ntfs::volumes::{
+    Volume,
+    VolumeKind,
+};
+use ntfs::volume_source::{
+    LiveVolume,
+    read_mft,
+    VolumeSource,
+};
+use rayon::prelude::*;
 use slog::Logger;
-use sql::insert_files;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::SeekFrom;
+use sql;
+use sql::{
+    forget_volumes,
+    load_journal_state,
+    replace_files,
+};
 use std::path::Path;
 use std::thread;
 
 
-fn parse_volume<P: AsRef<Path>>(logger: Logger, path: P) -> Vec<FileRecord> {
+fn parse_volume<P: AsRef<Path>>(logger: Logger, path: P, policy: FixupPolicy) -> Result<(Vec<FileRecord>, ParseReport), Error> {
     info!(logger, "parse volume"; "status" => "started");
-    let (mft, volume) = read_mft(path.as_ref());
+    let mut volume = LiveVolume::open(path.as_ref())?;
+    let volume_data = volume.volume_data();
+    let mft = read_mft(&mut volume)?;
 
-    let mut parser = MftParser::new(logger.clone(), &mft, volume);
-    let mut reader = parser.new_reader(path, 42);
+    let mut parser = MftParser::new(logger.clone(), &mft, volume_data, policy);
+    let mut queue = IocpQueue::new(volume_data, logger.clone())?;
+    let mut reader = queue.new_reader(path, 42);
 
     let read_thread = thread::Builder::new().name("producer".to_string()).spawn(move || {
-        reader.read_all(&mft, volume);
+        reader.read_all(&mft, volume_data);
     }).unwrap();
-    parser.parse_iocp_buffer();
+    queue.parse_all(&mut parser);
     read_thread.join().expect("reader panic");
+    parser.finish();
     info!(logger, "parse volume"; "status" => "finished", "files count"=> parser.files.len());
-    parser.files
+    Ok((parser.files, parser.report))
 }
 
-fn read_mft<P: AsRef<Path>>(volume_path: P) -> (FileRecord, VolumeData) {
-    let mut file = File::open(volume_path).expect("Failed to open volume handle");
-    let volume_data = VolumeData::new(get_volume_data(&file).unwrap());
-    let mut buffer = vec![0u8; volume_data.bytes_per_file_record as usize];
-
-    file.seek(SeekFrom::Start(volume_data.initial_offset())).unwrap();
-    file.read_exact(&mut buffer).unwrap();
-    let mft = FileRecord::parse_mft_entry(&mut buffer, volume_data).unwrap();
-
-    (mft, volume_data)
+/// Brings the database up to date with every configured volume, scanning in parallel those that can't be resumed.
+/// Directory trees are left alone, they are walked when their change source is opened.
+pub fn run(parent_logger: Logger, volumes: &[Volume], policy: FixupPolicy) -> Result<(), Error> {
+    forget_volumes(volumes.len());
+    volumes.par_iter()
+        .map(|volume| {
+            let logger = parent_logger.new(o!("type" =>"files", "volume" => volume.name.clone()));
+            if volume.kind == VolumeKind::DIRECTORY {
+                return Ok(());
+            }
+            let journal = volume.device_path().and_then(|path| current_journal(path));
+            match (load_journal_state(volume), journal) {
+                (Some(state), Ok(ref journal)) if state.can_resume(journal) => {
+                    info!(logger, "resume change journal"; "usn" => state.next_usn, "behind" => journal.next_usn - state.next_usn);
+                    Ok(())
+                }
+                (saved, journal) => {
+                    if let Err(e) = journal {
+                        warn!(logger, "change journal can't be queried, rescanning"; "error" => e.to_string());
+                    } else if let Some(state) = saved {
+                        warn!(logger, "change journal can't be resumed, rescanning"; "journal id" => state.usn_journal_id, "usn" => state.next_usn);
+                    }
+                    reindex(&logger, volume, policy)
+                }
+            }
+        })
+        .collect()
 }
 
-pub fn run(parent_logger: Logger) -> Result<(), Error> {
-    let volume_path = "\\\\.\\C:";
-    let logger = parent_logger.new(o!("type" =>"files", "volume" => volume_path.to_string()));
-    if !Path::new("./test.db").exists() {
-        let files = parse_volume(logger, volume_path);
-        insert_files(&files);
+/// Scans the whole volume again and replaces what the database holds for it with the result.
+pub fn reindex(logger: &Logger, volume: &Volume, policy: FixupPolicy) -> Result<(), Error> {
+    let volume_path = volume.device_path()?;
+    // the journal is queried before the scan, so changes made during the scan are replayed too
+    let journal = match current_journal(&volume_path) {
+        Ok(journal) => Some(JournalState::from(journal)),
+        Err(e) => {
+            warn!(logger, "change journal can't be queried, changes will only be picked up by rescans"; "error" => e.to_string());
+            None
+        }
+    };
+    let (files, report) = parse_volume(logger.clone(), &volume_path, policy)?;
+    if !report.torn_records.is_empty() {
+        warn!(logger, "indexed records that failed fixup verification"; "records" => format!("{:?}", report.torn_records));
     }
+    replace_files(&mut sql::main(), volume, &files, journal);
     Ok(())
 }
@@ -1,8 +1,15 @@
 use byteorder::{ByteOrder, LittleEndian};
+use errors::MyErrorKind::InvalidBootSector;
+use failure::Error;
+
+pub const BOOT_SECTOR_SIZE: usize = 512;
+const NTFS_OEM_ID: &[u8] = b"NTFS    ";
+const BOOT_SIGNATURE: u16 = 0xAA55;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct VolumeData {
     pub mft_start_lcn: u64,
+    pub mft_mirror_lcn: u64,
     pub bytes_per_cluster: u32,
     pub bytes_per_sector: u32,
     pub bytes_per_file_record: u32,
@@ -14,13 +21,52 @@ impl VolumeData {
         let bytes_per_cluster = LittleEndian::read_u32(&input[0x2C..]);
         let bytes_per_file_record = LittleEndian::read_u32(&input[0x30..]);
         let mft_start_lcn = LittleEndian::read_u64(&input[0x40..]);
+        let mft_mirror_lcn = LittleEndian::read_u64(&input[0x48..]);
         VolumeData {
             mft_start_lcn,
+            mft_mirror_lcn,
             bytes_per_file_record,
             bytes_per_sector,
             bytes_per_cluster,
         }
     }
+
+    pub fn from_boot_sector(input: &[u8]) -> Result<VolumeData, Error> {
+        if input.len() < BOOT_SECTOR_SIZE {
+            Err(InvalidBootSector("boot sector is too short"))?
+        }
+        if &input[0x03..0x0B] != NTFS_OEM_ID {
+            Err(InvalidBootSector("OEM id is not NTFS"))?
+        }
+        if LittleEndian::read_u16(&input[0x1FE..]) != BOOT_SIGNATURE {
+            Err(InvalidBootSector("missing 0xAA55 signature"))?
+        }
+        let bytes_per_sector = LittleEndian::read_u16(&input[0x0B..]) as u32;
+        if bytes_per_sector == 0 || !bytes_per_sector.is_power_of_two() {
+            Err(InvalidBootSector("invalid bytes per sector"))?
+        }
+        let sectors_per_cluster = match input[0x0D] {
+            0 => Err(InvalidBootSector("invalid sectors per cluster"))?,
+            v if v > 0x80 => 1u32.checked_shl(256 - v as u32).ok_or(InvalidBootSector("invalid sectors per cluster"))?,
+            v => v as u32,
+        };
+        let bytes_per_cluster = bytes_per_sector.checked_mul(sectors_per_cluster).ok_or(InvalidBootSector("invalid cluster size"))?;
+        let mft_start_lcn = LittleEndian::read_u64(&input[0x30..]);
+        let mft_mirror_lcn = LittleEndian::read_u64(&input[0x38..]);
+        let bytes_per_file_record = match input[0x40] as i8 {
+            v if v < 0 => 1u32.checked_shl(-(v as i32) as u32).ok_or(InvalidBootSector("invalid file record size"))?,
+            0 => Err(InvalidBootSector("invalid file record size"))?,
+            v => bytes_per_cluster.checked_mul(v as u32).ok_or(InvalidBootSector("invalid file record size"))?,
+        };
+        Ok(VolumeData {
+            mft_start_lcn,
+            mft_mirror_lcn,
+            bytes_per_file_record,
+            bytes_per_sector,
+            bytes_per_cluster,
+        })
+    }
+
     pub fn initial_offset(&self) -> u64 {
         self.bytes_per_cluster as u64 * self.mft_start_lcn
     }
@@ -34,10 +80,57 @@ impl VolumeData {
 mod tests {
     use super::*;
 
+    fn boot_sector(sectors_per_cluster: u8, clusters_per_fr: u8) -> [u8; 512] {
+        let mut input = [0u8; 512];
+        input[0x03..0x0B].copy_from_slice(NTFS_OEM_ID);
+        LittleEndian::write_u16(&mut input[0x0B..], 512);
+        input[0x0D] = sectors_per_cluster;
+        LittleEndian::write_u64(&mut input[0x30..], 786432);
+        LittleEndian::write_u64(&mut input[0x38..], 2);
+        input[0x40] = clusters_per_fr;
+        LittleEndian::write_u16(&mut input[0x1FE..], BOOT_SIGNATURE);
+        input
+    }
+
     #[test]
     fn test_data_attr() {
         let input = [206, 83, 254, 140, 132, 254, 140, 96, 255, 231, 245, 28, 0, 0, 0, 0, 255, 188, 158, 3, 0, 0, 0, 0, 1, 14, 94, 1, 0, 0, 0, 0, 95, 35, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 16, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 64, 133, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 192, 197, 247, 2, 0, 0, 0, 0, 224, 141, 248, 2, 0, 0, 0, 0, 32, 0, 0, 0, 3, 0, 1, 0, 0, 2, 0, 0, 2, 0, 0, 0, 0, 2, 0, 0, 255, 255, 255, 255, 62, 0, 0, 0, 0, 0, 0, 64];
-        let output = VolumeData { mft_start_lcn: 786432, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
+        let output = VolumeData { mft_start_lcn: 786432, mft_mirror_lcn: 2, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
         assert_eq!(output, VolumeData::new(input));
     }
+
+    #[test]
+    fn boot_sector_with_negative_file_record_size() {
+        let output = VolumeData { mft_start_lcn: 786432, mft_mirror_lcn: 2, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
+        assert_eq!(output, VolumeData::from_boot_sector(&boot_sector(8, 0xF6)).unwrap());
+    }
+
+    #[test]
+    fn boot_sector_with_positive_file_record_size() {
+        let volume_data = VolumeData::from_boot_sector(&boot_sector(2, 1)).unwrap();
+        assert_eq!(1024, volume_data.bytes_per_cluster);
+        assert_eq!(1024, volume_data.bytes_per_file_record);
+    }
+
+    #[test]
+    fn boot_sector_with_negative_sectors_per_cluster() {
+        let volume_data = VolumeData::from_boot_sector(&boot_sector(0xF4, 0xF6)).unwrap();
+        assert_eq!(512 * 4096, volume_data.bytes_per_cluster);
+        assert_eq!(1024, volume_data.bytes_per_file_record);
+    }
+
+    #[test]
+    fn boot_sector_requires_ntfs_oem_id() {
+        let mut input = boot_sector(8, 0xF6);
+        input[0x03..0x0B].copy_from_slice(b"MSDOS5.0");
+        assert!(VolumeData::from_boot_sector(&input).is_err());
+    }
+
+    #[test]
+    fn boot_sector_requires_signature() {
+        let mut input = boot_sector(8, 0xF6);
+        input[0x1FF] = 0;
+        assert!(VolumeData::from_boot_sector(&input).is_err());
+        assert!(VolumeData::from_boot_sector(&input[..256]).is_err());
+    }
 }
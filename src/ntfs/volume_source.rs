@@ -0,0 +1,282 @@
+use errors::MyErrorKind::VolumeReadError;
+use failure::{
+    Error,
+    ResultExt,
+};
+use ntfs::attributes::{
+    attribute_list_entries,
+    AttributeListAttr,
+};
+use ntfs::file_record::{
+    FileRecord,
+    FixupPolicy,
+};
+use ntfs::FR_AT_ONCE;
+use ntfs::mft_parser::{
+    MftParser,
+    ParseReport,
+};
+use ntfs::volume_data::{
+    BOOT_SECTOR_SIZE,
+    VolumeData,
+};
+#[cfg(windows)]
+use ntfs::windows_api::get_volume_data;
+use slog::Logger;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+pub trait VolumeSource {
+    fn volume_data(&self) -> VolumeData;
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), Error>;
+}
+
+#[cfg(windows)]
+pub struct LiveVolume {
+    file: File,
+    volume_data: VolumeData,
+}
+
+#[cfg(windows)]
+impl LiveVolume {
+    pub fn open<P: AsRef<Path>>(volume_path: P) -> Result<Self, Error> {
+        let file = File::open(volume_path).context(VolumeReadError)?;
+        let volume_data = get_volume_data(&file).map(VolumeData::new).context(VolumeReadError)?;
+        Ok(LiveVolume { file, volume_data })
+    }
+}
+
+#[cfg(windows)]
+impl LiveVolume {
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+#[cfg(windows)]
+impl VolumeSource for LiveVolume {
+    fn volume_data(&self) -> VolumeData {
+        self.volume_data
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        read_at(&mut self.file, offset, buffer)
+    }
+}
+
+pub struct ImageVolume {
+    file: File,
+    volume_data: VolumeData,
+}
+
+impl ImageVolume {
+    pub fn open<P: AsRef<Path>>(image_path: P) -> Result<Self, Error> {
+        let mut file = File::open(image_path).context(VolumeReadError)?;
+        let mut boot_sector = [0u8; BOOT_SECTOR_SIZE];
+        read_at(&mut file, 0, &mut boot_sector)?;
+        let volume_data = VolumeData::from_boot_sector(&boot_sector)?;
+        Ok(ImageVolume { file, volume_data })
+    }
+}
+
+impl VolumeSource for ImageVolume {
+    fn volume_data(&self) -> VolumeData {
+        self.volume_data
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        read_at(&mut self.file, offset, buffer)
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+    file.seek(SeekFrom::Start(offset)).context(VolumeReadError)?;
+    file.read_exact(buffer).context(VolumeReadError)?;
+    Ok(())
+}
+
+pub fn read_mft(source: &mut VolumeSource) -> Result<FileRecord, Error> {
+    let volume_data = source.volume_data();
+    let mut buffer = vec![0u8; volume_data.bytes_per_file_record as usize];
+    source.read_at(volume_data.initial_offset(), &mut buffer)?;
+    let mft = FileRecord::parse_mft_entry(&mut buffer, volume_data, FixupPolicy::Reject)?.ok_or(VolumeReadError)?;
+    Ok(mft)
+}
+
+/// Reads the entries of an attribute list kept outside its record, which is where big lists end up.
+pub fn load_attribute_list(source: &mut VolumeSource, list: &mut AttributeListAttr) -> Result<(), Error> {
+    if list.datarun.is_empty() || !list.entries.is_empty() {
+        return Ok(());
+    }
+    let bytes_per_cluster = source.volume_data().bytes_per_cluster as u64;
+    let mut data = Vec::with_capacity(list.size as usize);
+    let mut absolute_lcn_offset = 0i64;
+    for run in &list.datarun {
+        if data.len() as u64 >= list.size {
+            break;
+        }
+        absolute_lcn_offset += run.offset_lcn;
+        let start = data.len();
+        data.resize(start + (run.length_lcn * bytes_per_cluster) as usize, 0);
+        source.read_at(absolute_lcn_offset as u64 * bytes_per_cluster, &mut data[start..])?;
+    }
+    data.truncate(list.size as usize);
+    list.entries = attribute_list_entries(&data)?;
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MftChunk {
+    pub offset: u64,
+    pub fr_count: u64,
+}
+
+pub fn mft_chunks(mft: &FileRecord, volume_data: VolumeData) -> Vec<MftChunk> {
+    let bytes_per_fr = volume_data.bytes_per_file_record as u64;
+    let mut chunks = Vec::new();
+    let mut absolute_lcn_offset = 0i64;
+    for run in &mft.data_attr.datarun {
+        absolute_lcn_offset += run.offset_lcn;
+        let absolute_offset = absolute_lcn_offset as u64 * volume_data.bytes_per_cluster as u64;
+        let file_record_count = run.length_lcn * volume_data.bytes_per_cluster as u64 / bytes_per_fr;
+        let mut done = 0;
+        while done < file_record_count {
+            let fr_count = (file_record_count - done).min(FR_AT_ONCE);
+            chunks.push(MftChunk { offset: absolute_offset + done * bytes_per_fr, fr_count });
+            done += fr_count;
+        }
+    }
+    chunks
+}
+
+/// Parses the MFT of a raw image (dd) of an NTFS volume. Only plain file reads are involved, so it works on any platform.
+pub fn parse_image<P: AsRef<Path>>(logger: Logger, path: P, policy: FixupPolicy) -> Result<(Vec<FileRecord>, ParseReport), Error> {
+    info!(logger, "parse image"; "status" => "started");
+    let mut image = ImageVolume::open(path)?;
+    let (files, report) = parse_source(logger.clone(), &mut image, policy)?;
+    info!(logger, "parse image"; "status" => "finished", "files count"=> files.len());
+    Ok((files, report))
+}
+
+fn parse_source(logger: Logger, source: &mut VolumeSource, policy: FixupPolicy) -> Result<(Vec<FileRecord>, ParseReport), Error> {
+    let volume_data = source.volume_data();
+    let mft = read_mft(source)?;
+    let mut parser = MftParser::new(logger, &mft, volume_data, policy);
+
+    let bytes_per_fr = volume_data.bytes_per_file_record as usize;
+    let mut buffer = Vec::new();
+    for chunk in mft_chunks(&mft, volume_data) {
+        let fr_count = chunk.fr_count as usize;
+        buffer.resize(fr_count * bytes_per_fr, 0);
+        source.read_at(chunk.offset, &mut buffer)?;
+        parser.parse_buffer(&mut buffer, fr_count);
+    }
+    parser.finish();
+    Ok((parser.files, parser.report))
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{
+        ByteOrder,
+        LittleEndian,
+    };
+    use ntfs::attributes::{
+        Datarun,
+        DATA,
+        FILENAME,
+        STANDARD,
+    };
+    use slog::Discard;
+    use super::*;
+
+    const VOLUME_DATA: VolumeData = VolumeData { mft_start_lcn: 4, mft_mirror_lcn: 2, bytes_per_cluster: 4096, bytes_per_sector: 512, bytes_per_file_record: 1024 };
+
+    struct MemoryVolume(Vec<u8>);
+
+    impl VolumeSource for MemoryVolume {
+        fn volume_data(&self) -> VolumeData {
+            VOLUME_DATA
+        }
+
+        fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+            let offset = offset as usize;
+            buffer.copy_from_slice(&self.0[offset..offset + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    fn list_entry(attr_type: u32, record: u64) -> Vec<u8> {
+        let mut entry = vec![0u8; 0x20];
+        LittleEndian::write_u32(&mut entry[0x00..], attr_type);
+        LittleEndian::write_u16(&mut entry[0x04..], 0x20);
+        entry[0x07] = 0x1A;
+        LittleEndian::write_u64(&mut entry[0x10..], record | 5 << 48);
+        entry
+    }
+
+    // 4 KiB clusters and 1 KiB records, the MFT is one run of 4 clusters at LCN 4. Record 14 (torn.txt) fails
+    // fixup verification and record 15 (deleted.txt) isn't in use.
+    const TINY_IMAGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tiny_ntfs.img");
+
+    fn names(files: &[FileRecord]) -> Vec<&str> {
+        files.iter().map(|f| f.name_attrs[0].name.as_str()).collect()
+    }
+
+    #[test]
+    fn mft_chunks_follow_dataruns() {
+        let volume_data = VOLUME_DATA;
+        let mut mft = FileRecord::default();
+        mft.data_attr.datarun = vec![
+            Datarun { length_lcn: 20, offset_lcn: 4 },
+            Datarun { length_lcn: 1, offset_lcn: -2 }];
+        let expected = vec![
+            MftChunk { offset: 4 * 4096, fr_count: FR_AT_ONCE },
+            MftChunk { offset: 4 * 4096 + FR_AT_ONCE * 1024, fr_count: 80 - FR_AT_ONCE },
+            MftChunk { offset: 2 * 4096, fr_count: 4 }];
+        assert_eq!(expected, mft_chunks(&mft, volume_data));
+    }
+
+    #[test]
+    fn parses_image() {
+        let (files, report) = parse_image(Logger::root(Discard, o!()), TINY_IMAGE, FixupPolicy::Warn).unwrap();
+        assert_eq!(vec!["$MFT", ".", "docs", "readme.txt", "notes.txt", "torn.txt"], names(&files));
+        assert!(files[2].is_directory());
+        assert_eq!(5, files[3].data_attr.size);
+        assert_eq!(11, files[4].name_attrs[0].parent_id & 0xFFFF_FFFF_FFFF);
+        assert_eq!(vec![14], report.torn_records);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn rejects_torn_records_of_image() {
+        let (files, report) = parse_image(Logger::root(Discard, o!()), TINY_IMAGE, FixupPolicy::Reject).unwrap();
+        assert_eq!(vec!["$MFT", ".", "docs", "readme.txt", "notes.txt"], names(&files));
+        assert_eq!(1, report.errors.len());
+        assert!(report.torn_records.is_empty());
+    }
+
+    #[test]
+    fn loads_non_resident_attribute_list() {
+        // the list spans two runs, the second one is read only up to the size of the list
+        let entries = (0..130).map(|i| list_entry(DATA, 43 + i % 3)).collect::<Vec<_>>();
+        let list = [list_entry(STANDARD, 42), list_entry(FILENAME, 44)].iter().chain(&entries).flat_map(|e| e.clone()).collect::<Vec<u8>>();
+        let mut volume = vec![0u8; 8 * 4096];
+        volume[2 * 4096..3 * 4096].copy_from_slice(&list[..4096]);
+        volume[6 * 4096..6 * 4096 + list.len() - 4096].copy_from_slice(&list[4096..]);
+        let mut file = FileRecord::default();
+        file.header.fr_number = 42;
+        file.attribute_list = Some(AttributeListAttr {
+            entries: Vec::new(),
+            size: list.len() as u64,
+            datarun: vec![Datarun { length_lcn: 1, offset_lcn: 2 }, Datarun { length_lcn: 2, offset_lcn: 4 }],
+        });
+
+        load_attribute_list(&mut MemoryVolume(volume), file.attribute_list.as_mut().unwrap()).unwrap();
+        assert_eq!(132, file.attribute_list.as_ref().unwrap().entries.len());
+        assert_eq!(vec![43, 44, 45], file.extension_records());
+    }
+}
@@ -0,0 +1,101 @@
+use failure::Error;
+use ntfs::windows_api::volume_guid_path;
+
+const SEPARATOR: char = '\\';
+
+/// How a volume is indexed: NTFS volumes by reading their MFT, anything else by walking its directories.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VolumeKind {
+    NTFS,
+    DIRECTORY,
+}
+
+/// A volume of the configured list, `index` is its position in the list and tags every `FileId` read from it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Volume {
+    pub index: u8,
+    pub name: String,
+    pub kind: VolumeKind,
+}
+
+impl Volume {
+    /// Drive letters are opened as `\\.\C:`, mount points through the GUID path of the volume mounted there.
+    pub fn device_path(&self) -> Result<String, Error> {
+        if is_drive_letter(&self.name) {
+            Ok(format!("\\\\.\\{}", self.name))
+        } else {
+            volume_guid_path(&format!("{}{}", self.name, SEPARATOR))
+        }
+    }
+}
+
+/// Parses the comma separated `Volumes` setting, e.g. `C:,D:,E:\mnt\data`, followed by the `Directories`
+/// setting listing the trees to walk, e.g. `F:,\\server\share`. Duplicates are dropped.
+pub fn parse_volumes(volumes: &str, directories: &str) -> Vec<Volume> {
+    let mut found: Vec<(String, VolumeKind)> = Vec::new();
+    let entries = volumes.split(',').map(|v| (v, VolumeKind::NTFS))
+        .chain(directories.split(',').map(|d| (d, VolumeKind::DIRECTORY)));
+    for (name, kind) in entries {
+        let name = trim_separators(name.trim());
+        if name.is_empty() {
+            continue;
+        }
+        let name = if is_drive_letter(name) { name.to_uppercase() } else { name.to_string() };
+        if !found.iter().any(|(n, _)| *n == name) {
+            found.push((name, kind));
+        }
+    }
+    found.into_iter()
+        .take(u8::max_value() as usize + 1)
+        .enumerate()
+        .map(|(index, (name, kind))| Volume { index: index as u8, name, kind })
+        .collect()
+}
+
+/// A filesystem root like `/` is kept whole.
+fn trim_separators(name: &str) -> &str {
+    let trimmed = name.trim_right_matches(|c| c == SEPARATOR || c == '/');
+    if trimmed.is_empty() { &name[..name.len().min(1)] } else { trimmed }
+}
+
+fn is_drive_letter(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 2 && (bytes[0] as char).is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ntfs(index: u8, name: &str) -> Volume {
+        Volume { index, name: name.to_string(), kind: VolumeKind::NTFS }
+    }
+
+    fn directory(index: u8, name: &str) -> Volume {
+        Volume { index, name: name.to_string(), kind: VolumeKind::DIRECTORY }
+    }
+
+    #[test]
+    fn parses_drive_letters_in_order() {
+        let volumes = parse_volumes("c:, D:\\ ,E:", "");
+        assert_eq!(vec![ntfs(0, "C:"), ntfs(1, "D:"), ntfs(2, "E:")], volumes);
+    }
+
+    #[test]
+    fn drops_duplicates_and_empty_entries() {
+        let volumes = parse_volumes("C:,,c:,C:\\mnt\\data\\", "");
+        assert_eq!(vec![ntfs(0, "C:"), ntfs(1, "C:\\mnt\\data")], volumes);
+    }
+
+    #[test]
+    fn directories_follow_volumes() {
+        let volumes = parse_volumes("C:", "f:\\, /home/me/, C:, /");
+        assert_eq!(vec![ntfs(0, "C:"), directory(1, "F:"), directory(2, "/home/me"), directory(3, "/")], volumes);
+    }
+
+    #[test]
+    fn drive_letter_device_path() {
+        let volume = ntfs(0, "D:");
+        assert_eq!("\\\\.\\D:", volume.device_path().unwrap());
+    }
+}
@@ -1,14 +1,19 @@
 use byteorder::{ByteOrder, LittleEndian};
 use errors::MyErrorKind::*;
 use failure::{Error, ResultExt};
+use ntfs::change_journal::JournalLoss;
 use ntfs::windows_api::structs::*;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
 use std::mem;
+use std::os::windows::ffi::OsStrExt;
 use std::os::windows::io::AsRawHandle;
 use std::ptr;
 use winapi::ctypes::c_void;
 use winapi::shared::minwindef::BYTE;
+use winapi::shared::winerror::ERROR_JOURNAL_ENTRY_DELETED;
+use winapi::um::fileapi::GetVolumeNameForVolumeMountPointW;
 use winapi::um::ioapiset::DeviceIoControl;
 use winapi::um::winioctl::{
     FSCTL_GET_NTFS_FILE_RECORD,
@@ -71,20 +76,27 @@ pub fn get_file_record<'a>(v_handle: &File, fr_number: i64, buffer: &'a mut [u8]
 
 pub fn read_usn_journal<'a>(v_handle: &File, start_at: i64, journal_id: u64, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
     let mut bytes_read = 0;
-    let mut x = ReadUsnJournalDataV0::new(start_at, journal_id);
+    let mut x = ReadUsnJournalDataV1::new(start_at, journal_id);
     match unsafe {
         DeviceIoControl(
             v_handle.as_raw_handle(),
             FSCTL_READ_USN_JOURNAL,
             &mut x as *mut _ as *mut c_void,
-            mem::size_of::<ReadUsnJournalDataV0>() as u32,
+            mem::size_of::<ReadUsnJournalDataV1>() as u32,
             buf.as_mut_ptr() as *mut _,
             buf.len() as u32,
             &mut bytes_read,
             ptr::null_mut(),
         )
     } {
-        v if v == 0 => Err(io::Error::last_os_error()).context(WindowsError("Failed to read usn_journal"))?,
+        v if v == 0 => {
+            let error = io::Error::last_os_error();
+            // the records from `start_at` on were purged, reading again won't bring them back
+            if error.raw_os_error() == Some(ERROR_JOURNAL_ENTRY_DELETED as i32) {
+                Err(JournalLoss::Wrapped(start_at))?
+            }
+            Err(error).context(WindowsError("Failed to read usn_journal"))?
+        }
         _ => Ok(&buf[..bytes_read as usize]),
     }
 }
@@ -107,12 +119,30 @@ pub fn get_usn_journal(v_handle: &File) -> Result<UsnJournal, Error> {
     }
     if bytes_read == 80 {
         let usn_journal_id = LittleEndian::read_u64(&output);
+        let first_usn = LittleEndian::read_i64(&output[8..]);
         let next_usn = LittleEndian::read_i64(&output[16..]);
+        let lowest_valid_usn = LittleEndian::read_i64(&output[24..]);
         Ok(UsnJournal {
             usn_journal_id,
+            first_usn,
+            lowest_valid_usn,
             next_usn,
         })
     } else {
         Err(WindowsError("Failed to query usn_journal"))?
     }
-}
\ No newline at end of file
+}
+
+/// `\\?\Volume{GUID}` of the volume mounted at `mount_point`, which must end with a backslash.
+pub fn volume_guid_path(mount_point: &str) -> Result<String, Error> {
+    let mount_point = OsStr::new(mount_point).encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+    let mut output = [0u16; 50];
+    match unsafe { GetVolumeNameForVolumeMountPointW(mount_point.as_ptr(), output.as_mut_ptr(), output.len() as u32) } {
+        v if v == 0 => Err(io::Error::last_os_error()).context(WindowsError("Failed to get volume name of mount point"))?,
+        _ => {
+            let len = output.iter().take_while(|&&c| c != 0).count();
+            let path = String::from_utf16_lossy(&output[..len]);
+            Ok(path.trim_right_matches('\\').to_string())
+        }
+    }
+}
@@ -1,18 +1,18 @@
 use byteorder::{ByteOrder, LittleEndian};
+#[cfg(windows)]
 pub use self::api_calls::*;
 pub use self::structs::*;
-use std::ffi::OsString;
-use std::os::windows::ffi::OsStringExt;
+use std::char;
 
 mod structs;
+#[cfg(windows)]
 mod api_calls;
 
 
+/// Decodes the UTF-16LE names stored on disk. Unpaired surrogates, which NTFS allows, become U+FFFD.
 pub fn windows_string(input: &[u8]) -> String {
-    let mut x: Vec<u16> = vec![];
-    for c in input.chunks(2) {
-        x.push(LittleEndian::read_u16(c));
-    }
-    OsString::from_wide(&x[..]).into_string().unwrap()
+    let wide = input.chunks(2).map(LittleEndian::read_u16);
+    char::decode_utf16(wide)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
 }
-
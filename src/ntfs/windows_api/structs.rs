@@ -3,15 +3,38 @@ use std::fmt;
 #[derive(Copy, Clone, Debug)]
 pub struct UsnJournal {
     pub usn_journal_id: u64,
+    /// The oldest record still in the journal, it moves up as old records are purged.
+    pub first_usn: i64,
+    /// Where the current instance of the journal starts, it only moves when the journal is recreated or stamped.
+    pub lowest_valid_usn: i64,
     pub next_usn: i64,
 }
 
 bitflags! {
     pub struct WinUsnChanges: u32 {
+        const DATA_OVERWRITE= 0x00000001;
+        const DATA_EXTEND= 0x00000002;
+        const DATA_TRUNCATION= 0x00000004;
+        const NAMED_DATA_OVERWRITE= 0x00000010;
+        const NAMED_DATA_EXTEND= 0x00000020;
+        const NAMED_DATA_TRUNCATION= 0x00000040;
         const FILE_CREATE= 0x00000100;
         const FILE_DELETE= 0x00000200;
+        const EA_CHANGE= 0x00000400;
+        const SECURITY_CHANGE= 0x00000800;
+        const RENAME_OLD_NAME= 0x00001000;
         const RENAME_NEW_NAME= 0x00002000;
+        const INDEXABLE_CHANGE= 0x00004000;
         const BASIC_INFO_CHANGE= 0x00008000;
+        const HARD_LINK_CHANGE= 0x00010000;
+        const COMPRESSION_CHANGE= 0x00020000;
+        const ENCRYPTION_CHANGE= 0x00040000;
+        const OBJECT_ID_CHANGE= 0x00080000;
+        const REPARSE_POINT_CHANGE= 0x00100000;
+        const STREAM_CHANGE= 0x00200000;
+        const TRANSACTED_CHANGE= 0x00400000;
+        const INTEGRITY_CHANGE= 0x00800000;
+        const DESIRED_STORAGE_CLASS_CHANGE= 0x01000000;
         const CLOSE= 0x80000000;
     }
 }
@@ -23,24 +46,29 @@ impl fmt::Display for WinUsnChanges {
 }
 
 #[repr(C)]
-pub struct ReadUsnJournalDataV0 {
+pub struct ReadUsnJournalDataV1 {
     start: i64,
     reason_mask: u32,
     return_only_on_close: u32,
     timeout: u64,
     bytes_to_wait_for: u64,
     usn_journal_id: u64,
+    min_major_version: u16,
+    max_major_version: u16,
 }
 
-impl ReadUsnJournalDataV0 {
+impl ReadUsnJournalDataV1 {
     pub fn new(start: i64, usn_journal_id: u64) -> Self {
-        ReadUsnJournalDataV0 {
+        ReadUsnJournalDataV1 {
             start,
             reason_mask: WinUsnChanges::all().bits(),
-            return_only_on_close: 1,
+            // renames are only told apart by the RENAME_OLD_NAME record, which is never a close record
+            return_only_on_close: 0,
             timeout: 1,
             bytes_to_wait_for: 1,
             usn_journal_id,
+            min_major_version: 2,
+            max_major_version: 4,
         }
     }
 }
\ No newline at end of file
@@ -23,6 +23,10 @@ pub enum Setting {
     ColumnFileNameWidth,
     ColumnFilePathWidth,
     ColumnFileSizeWidth,
+    ShowStreams,
+    Volumes,
+    Directories,
+    RejectTornRecords,
 }
 
 impl Setting {
@@ -36,6 +40,10 @@ impl Setting {
             Setting::ColumnFileNameWidth => "50",
             Setting::ColumnFilePathWidth => "50",
             Setting::ColumnFileSizeWidth => "50",
+            Setting::ShowStreams => "false",
+            Setting::Volumes => "C:",
+            Setting::Directories => "",
+            Setting::RejectTornRecords => "false",
         }
     }
 }
@@ -112,6 +120,10 @@ impl UserSettings {
     }
 }
 
+pub fn setting_to_bool(setting: Setting, settings: &HashMap<Setting, String>) -> bool {
+    settings.get(&setting).map(String::as_str).unwrap_or(setting.default_value()) == "true"
+}
+
 pub fn setting_to_int(setting: Setting, settings: &HashMap<Setting, String>) -> i32 {
     settings.get(&setting).map(|s| s.parse().expect("Setting is not an int")).expect("Setting not found")
 }
\ No newline at end of file
@@ -1,9 +1,23 @@
 use file_listing::file_entity::FileEntity;
+use file_listing::file_entity::LinkKind;
 use file_listing::files::Files;
+use ntfs::change_journal::JournalState;
+use ntfs::change_journal::UsnChange;
 use ntfs::file_record::FileRecord;
+use ntfs::volumes::Volume;
+use rusqlite::CachedStatement;
 use rusqlite::Connection;
 use rusqlite::Result;
+use std::path::Path;
+use std::time::Duration;
 
+// bump it whenever a table changes, databases of other versions are dropped and their volumes scanned again
+const SCHEMA_VERSION: i32 = 1;
+const DROP_DB: &str = "
+    DROP TABLE IF EXISTS file_entry;
+    DROP TABLE IF EXISTS stream_entry;
+    DROP TABLE IF EXISTS journal_state;
+    ";
 const CREATE_DB: &str = "
     CREATE TABLE IF NOT EXISTS file_entry (
     _id           INTEGER PRIMARY KEY,
@@ -17,28 +31,77 @@ const CREATE_DB: &str = "
     flags         INTEGER,
     base_record   INTEGER,
     fr_number     INTEGER,
-    namespace     INTEGER );
+    namespace     INTEGER,
+    link          INTEGER,
+    link_kind     INTEGER,
+    link_target   TEXT,
+    accessed_date INTEGER,
+    mft_changed_date INTEGER,
+    dos_attributes INTEGER,
+    owner_id      INTEGER,
+    security_id   INTEGER,
+    quota_charged INTEGER,
+    usn           INTEGER,
+    stream_count  INTEGER,
+    volume        INTEGER );
+    CREATE TABLE IF NOT EXISTS stream_entry (
+    id            INTEGER,
+    stream        INTEGER,
+    name          TEXT,
+    size          INTEGER,
+    volume        INTEGER );
+    CREATE TABLE IF NOT EXISTS journal_state (
+    volume         INTEGER PRIMARY KEY,
+    name           TEXT,
+    usn_journal_id INTEGER,
+    next_usn       INTEGER );
     ";
-const INSERT_FILE: &str = "INSERT INTO file_entry (id, parent_id, dos_flags, real_size, name, modified_date, created_date, flags, base_record, fr_number, namespace) \
-    VALUES (:id, :parent_id, :dos_flags, :real_size, :name, :modified_date, :created_date, :flags, :base_record, :fr_number, :namespace);";
+const INSERT_FILE: &str = "INSERT INTO file_entry (id, parent_id, dos_flags, real_size, name, modified_date, created_date, flags, base_record, fr_number, namespace, link, link_kind, link_target, \
+    accessed_date, mft_changed_date, dos_attributes, owner_id, security_id, quota_charged, usn, stream_count, volume) \
+    VALUES (:id, :parent_id, :dos_flags, :real_size, :name, :modified_date, :created_date, :flags, :base_record, :fr_number, :namespace, :link, :link_kind, :link_target, \
+    :accessed_date, :mft_changed_date, :dos_attributes, :owner_id, :security_id, :quota_charged, :usn, :stream_count, :volume);";
+const INSERT_STREAM: &str = "INSERT INTO stream_entry (id, stream, name, size, volume) VALUES (:id, :stream, :name, :size, :volume);";
 const UPSERT_FILE: &str = "INSERT OR REPLACE INTO file_entry (id, parent_id, dos_flags, real_size, name, modified_date, created_date) \
     VALUES (:id, :parent_id, :dos_flags, :real_size, :name, :modified_date, :created_date);";
 const UPDATE_FILE: &str = "UPDATE file_entry SET \
     id = :id, parent_id = :parent_id, dos_flags = :dos_flags, real_size = :real_size, name = :name, modified_date = :modified_date, created_date = :created_date \
     WHERE id = :id;";
 const DELETE_FILE: &str = "DELETE FROM file_entry WHERE id = :id;";
+const DELETE_VOLUME_FILE: &str = "DELETE FROM file_entry WHERE volume = :volume AND id = :id;";
+const DELETE_LINK: &str = "DELETE FROM file_entry WHERE volume = :volume AND id = :id AND parent_id = :parent_id AND name = :name;";
+const DELETE_STREAMS: &str = "DELETE FROM stream_entry WHERE volume = :volume AND id = :id;";
+const DELETE_VOLUME: [&str; 3] = [
+    "DELETE FROM file_entry WHERE volume = :volume;",
+    "DELETE FROM stream_entry WHERE volume = :volume;",
+    "DELETE FROM journal_state WHERE volume = :volume;"];
+const DELETE_VOLUMES_FROM: [&str; 3] = [
+    "DELETE FROM file_entry WHERE volume >= :volume;",
+    "DELETE FROM stream_entry WHERE volume >= :volume;",
+    "DELETE FROM journal_state WHERE volume >= :volume;"];
+const SELECT_JOURNAL_STATE: &str = "SELECT usn_journal_id, next_usn FROM journal_state WHERE volume = :volume AND name = :name;";
+const SAVE_JOURNAL_STATE: &str = "INSERT OR REPLACE INTO journal_state (volume, name, usn_journal_id, next_usn) VALUES (:volume, :name, :usn_journal_id, :next_usn);";
 const COUNT_FILES: &str = "SELECT COUNT(id) FROM file_entry where name like :name";
 const SELECT_FILES: &str = "SELECT name, parent_id, real_size, id FROM file_entry where name like :name order by name limit :p_size;";
 const SELECT_COUNT_ALL: &str = "SELECT COUNT(id) FROM file_entry;";
 const SELECT_ALL_FILES: &str = "SELECT * FROM file_entry;";
+const SELECT_ALL_STREAMS: &str = "SELECT f.fr_number, s.stream, s.name, s.size, f.parent_id, f.name, f.flags, f.modified_date, f.created_date, f.accessed_date, f.dos_attributes, s.volume \
+    FROM stream_entry s JOIN file_entry f ON f.volume = s.volume AND f.id = s.id AND f.link = 0;";
+const SELECT_VOLUME_FILES: &str = "SELECT * FROM file_entry WHERE volume = :volume;";
+const SELECT_VOLUME_STREAMS: &str = "SELECT f.fr_number, s.stream, s.name, s.size, f.parent_id, f.name, f.flags, f.modified_date, f.created_date, f.accessed_date, f.dos_attributes, s.volume \
+    FROM stream_entry s JOIN file_entry f ON f.volume = s.volume AND f.id = s.id AND f.link = 0 WHERE s.volume = :volume;";
 const SELECT_FILES_NEXT_PAGE: &str = "SELECT name, parent_id, real_size, id FROM file_entry where name like :name and (name, id) >= (:p_name, :p_id) order by name limit :p_size;";
 //const FILE_ENTRY_NAME_INDEX: &str = "CREATE INDEX IF NOT EXISTS file_entry_name ON file_entry(name, id);";
 
 //const FILE_PAGE_SIZE: u32 = 3000;
 
 pub fn main() -> Connection {
-    let conn = Connection::open("test.db").unwrap();
-//    let conn = Connection::open_in_memory().unwrap();
+    setup(Connection::open("test.db").unwrap())
+}
+
+/// Creates the tables `conn` lacks and rebuilds them when they are from another schema version.
+pub fn setup(conn: Connection) -> Connection {
+    // every volume is scanned and followed by its own thread, each with its own connection
+    conn.busy_timeout(Duration::from_secs(60)).unwrap();
 
     conn.query_row("PRAGMA encoding;", &[], |row| {
         let x: String = row.get(0);
@@ -46,8 +109,18 @@ pub fn main() -> Connection {
     }).unwrap();
 
 
-    conn.execute(CREATE_DB, &[]).unwrap();
+    if schema_version(&conn) != SCHEMA_VERSION {
+        conn.execute_batch("BEGIN IMMEDIATE;").unwrap();
+        // another connection may have rebuilt it while this one waited for the lock
+        if schema_version(&conn) != SCHEMA_VERSION {
+            conn.execute_batch(DROP_DB).unwrap();
+            conn.execute_batch(&format!("PRAGMA user_version = {};", SCHEMA_VERSION)).unwrap();
+        }
+        conn.execute_batch("COMMIT;").unwrap();
+    }
+    conn.execute_batch(CREATE_DB).unwrap();
     conn.prepare_cached(INSERT_FILE).unwrap();
+    conn.prepare_cached(INSERT_STREAM).unwrap();
     conn.prepare_cached(UPDATE_FILE).unwrap();
     conn.prepare_cached(DELETE_FILE).unwrap();
     conn.prepare_cached(UPSERT_FILE).unwrap();
@@ -57,6 +130,10 @@ pub fn main() -> Connection {
     conn
 }
 
+fn schema_version(conn: &Connection) -> i32 {
+    conn.query_row("PRAGMA user_version;", &[], |row| row.get(0)).unwrap()
+}
+
 //pub fn delete_file(tx: &Transaction, file_id: u32) {
 //    tx.execute_named(DELETE_FILE, &[
 //        (":id", &file_id)]).unwrap();
@@ -90,32 +167,159 @@ pub fn main() -> Connection {
 //    con.execute(FILE_ENTRY_NAME_INDEX, &[]).unwrap();
 //}
 
-pub fn insert_files(files: &[FileRecord]) {
-    let mut conn = main();
+/// Replaces whatever the database holds for `volume` with a fresh scan of it, read from `state` on.
+/// Volumes without a change journal have no `state`.
+pub fn replace_files(conn: &mut Connection, volume: &Volume, files: &[FileRecord], state: Option<JournalState>) {
     let tx = conn.transaction().unwrap();
+    for delete in &DELETE_VOLUME {
+        tx.execute_named(delete, &[(":volume", &volume.index)]).unwrap();
+    }
     {
         let mut stmt = tx.prepare_cached(INSERT_FILE).unwrap();
+        let mut stream_stmt = tx.prepare_cached(INSERT_STREAM).unwrap();
         for file in files {
-            &file.name_attrs.iter().filter(|n| n.namespace != 2).for_each(|name| {
-                stmt.execute_named(&[
-                    (":id", &file.header.fr_number),
-                    (":parent_id", &(name.parent_id as u32)),
-                    (":dos_flags", &name.dos_flags),
-                    (":real_size", &file.data_attr.size),
-                    (":name", &name.name),
-                    (":modified_date", &file.standard_attr.modified),
-                    (":created_date", &file.standard_attr.created),
-                    (":base_record", &(file.header.base_record as i64)),
-                    (":fr_number", &file.fr_number()),
-                    (":namespace", &name.namespace),
-                    (":flags", &file.header.flags)]).unwrap();
-            });
+            insert_file(&mut stmt, &mut stream_stmt, volume.index, file);
+        }
+        if let Some(state) = state {
+            save_journal_state(&mut tx.prepare_cached(SAVE_JOURNAL_STATE).unwrap(), volume, state);
         }
     }
     tx.commit().unwrap();
 }
 
-pub fn load_all_arena() -> Result<(Files)> {
+/// Drops what was indexed for volumes numbered `count` and above, which are no longer configured.
+pub fn forget_volumes(count: usize) {
+    let mut conn = main();
+    let tx = conn.transaction().unwrap();
+    for delete in &DELETE_VOLUMES_FROM {
+        tx.execute_named(delete, &[(":volume", &(count as u32))]).unwrap();
+    }
+    tx.commit().unwrap();
+}
+
+/// Writes a batch of journal changes together with the position reached, so a restart resumes right after them.
+/// Volumes without a change journal have no `state`.
+pub fn apply_changes(conn: &mut Connection, volume: &Volume, changes: &[UsnChange], state: Option<JournalState>) {
+    let tx = conn.transaction().unwrap();
+    {
+        let mut stmt = tx.prepare_cached(INSERT_FILE).unwrap();
+        let mut stream_stmt = tx.prepare_cached(INSERT_STREAM).unwrap();
+        let mut delete_stmt = tx.prepare_cached(DELETE_VOLUME_FILE).unwrap();
+        let mut delete_link_stmt = tx.prepare_cached(DELETE_LINK).unwrap();
+        let mut delete_streams_stmt = tx.prepare_cached(DELETE_STREAMS).unwrap();
+        for change in changes {
+            let file = match *change {
+                UsnChange::NEW(ref file) | UsnChange::UPDATE(ref file) => file,
+                UsnChange::RENAME(ref rename) | UsnChange::MOVE(ref rename) => &rename.file,
+                UsnChange::DELETE(ref record) => {
+                    delete_link_stmt.execute_named(&[
+                        (":volume", &volume.index),
+                        (":id", &(record.mft_id as i64)),
+                        (":parent_id", &record.parent_fr_number),
+                        (":name", &record.name)]).unwrap();
+                    delete_streams_stmt.execute_named(&[(":volume", &volume.index), (":id", &(record.mft_id as i64))]).unwrap();
+                    continue;
+                }
+                UsnChange::IGNORE => continue,
+            };
+            delete_stmt.execute_named(&[(":volume", &volume.index), (":id", &(file.header.fr_number as i64))]).unwrap();
+            delete_streams_stmt.execute_named(&[(":volume", &volume.index), (":id", &(file.header.fr_number as i64))]).unwrap();
+            insert_file(&mut stmt, &mut stream_stmt, volume.index, file);
+        }
+        if let Some(state) = state {
+            save_journal_state(&mut tx.prepare_cached(SAVE_JOURNAL_STATE).unwrap(), volume, state);
+        }
+    }
+    tx.commit().unwrap();
+}
+
+/// None when the volume was never indexed, or another volume was configured at its position.
+pub fn load_journal_state(volume: &Volume) -> Option<JournalState> {
+    if !Path::new("test.db").exists() {
+        return None;
+    }
+    let conn = main();
+    let mut stmt = conn.prepare(SELECT_JOURNAL_STATE).unwrap();
+    let mut rows = stmt.query_named(&[(":volume", &volume.index), (":name", &volume.name)]).unwrap();
+    match rows.next() {
+        Some(Ok(r)) => Some(JournalState {
+            usn_journal_id: r.get::<i32, i64>(0) as u64,
+            next_usn: r.get(1),
+        }),
+        _ => None,
+    }
+}
+
+fn save_journal_state(stmt: &mut CachedStatement, volume: &Volume, state: JournalState) {
+    stmt.execute_named(&[
+        (":volume", &volume.index),
+        (":name", &volume.name),
+        (":usn_journal_id", &(state.usn_journal_id as i64)),
+        (":next_usn", &state.next_usn)]).unwrap();
+}
+
+fn insert_file(stmt: &mut CachedStatement, stream_stmt: &mut CachedStatement, volume: u8, file: &FileRecord) {
+    let stream_count = file.streams.len() as u16;
+    let link_kind = file.reparse_attr.as_ref().map_or(LinkKind::NONE, LinkKind::from);
+    let link_target = file.reparse_attr.as_ref().and_then(|r| r.target());
+    let standard = &file.standard_attr;
+    let quota_charged = standard.quota_charged.map(|q| q as i64);
+    &file.name_attrs.iter().filter(|n| n.namespace != 2).enumerate().for_each(|(link, name)| {
+        stmt.execute_named(&[
+            (":id", &(file.header.fr_number as i64)),
+            (":parent_id", &name.parent_id),
+            (":dos_flags", &name.dos_flags),
+            (":real_size", &file.data_attr.size),
+            (":name", &name.name),
+            (":modified_date", &standard.modified),
+            (":created_date", &standard.created),
+            (":accessed_date", &standard.accessed),
+            (":mft_changed_date", &standard.mft_changed),
+            (":dos_attributes", &standard.dos_attributes.bits()),
+            (":owner_id", &standard.owner_id),
+            (":security_id", &standard.security_id),
+            (":quota_charged", &quota_charged),
+            (":usn", &standard.usn),
+            (":stream_count", &stream_count),
+            (":base_record", &(file.header.base_record as i64)),
+            (":fr_number", &file.fr_number()),
+            (":namespace", &name.namespace),
+            (":link", &(link as u16)),
+            (":link_kind", &link_kind.code()),
+            (":link_target", &link_target),
+            (":flags", &file.header.flags),
+            (":volume", &volume)]).unwrap();
+    });
+    for (pos, stream) in file.streams.iter().enumerate() {
+        stream_stmt.execute_named(&[
+            (":id", &(file.header.fr_number as i64)),
+            (":stream", &(pos as u16)),
+            (":name", &stream.name),
+            (":size", &stream.size),
+            (":volume", &volume)]).unwrap();
+    }
+}
+
+/// What the database holds for `volume` alone, e.g. after it was scanned again.
+pub fn load_volume(volume: &Volume, show_streams: bool) -> Result<Vec<FileEntity>> {
+    let con = main();
+    let mut files = Vec::new();
+    {
+        let mut stmt = con.prepare(SELECT_VOLUME_FILES)?;
+        for file in stmt.query_map_named(&[(":volume", &volume.index)], FileEntity::from_file_row)? {
+            files.push(file??);
+        }
+    }
+    if show_streams {
+        let mut stmt = con.prepare(SELECT_VOLUME_STREAMS)?;
+        for stream in stmt.query_map_named(&[(":volume", &volume.index)], FileEntity::from_stream_row)? {
+            files.push(stream??);
+        }
+    }
+    Ok(files)
+}
+
+pub fn load_all_arena(volumes: &[Volume], show_streams: bool) -> Result<(Files)> {
     let con = Connection::open("test.db").unwrap();
     let count = con.query_row(SELECT_COUNT_ALL, &[], |r| r.get::<i32, u32>(0) as usize).unwrap();
     let mut stmt = con.prepare(SELECT_ALL_FILES).unwrap();
@@ -125,7 +329,14 @@ pub fn load_all_arena() -> Result<(Files)> {
         let f: FileEntity = file??;
         files.push(f);
     }
-    let mut arena = Files::new(count);
+    if show_streams {
+        let mut stmt = con.prepare(SELECT_ALL_STREAMS).unwrap();
+        let result = stmt.query_map(&[], FileEntity::from_stream_row).unwrap();
+        for stream in result {
+            files.push(stream??);
+        }
+    }
+    let mut arena = Files::new(count, show_streams, volumes.iter().map(|v| v.name.clone()).collect());
     arena.bulk_add(files);
     Ok(arena)
 }